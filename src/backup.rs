@@ -0,0 +1,292 @@
+use crate::cache::{self, StatCache};
+use crate::config::{Config, Entry};
+use crate::crypto;
+use crate::editor;
+use crate::files;
+use crate::filter;
+use crate::git;
+use crate::hash;
+use crate::json;
+use crate::lock;
+use crate::log;
+use crate::manifest::{self, Manifest};
+use crate::metadata::{self, Mtimes};
+use crate::metrics;
+use crate::notifications;
+use crate::ops::{self, Trigger};
+use crate::output;
+use crate::owner;
+use crate::push;
+use crate::storage::{self, Storage};
+use crate::store;
+use crate::trial;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct BackupReport {
+    pub files_changed: usize,
+    // The home directory paths behind `files_changed`.
+    pub files: Vec<PathBuf>,
+    pub files_moved: usize,
+    pub bytes_copied: u64,
+    pub committed: bool,
+    pub push_duration: Option<Duration>,
+}
+
+#[derive(Default)]
+pub struct BackupFlags {
+    pub unattended: bool,
+    pub trigger: Trigger,
+    pub quiet: bool,
+    pub no_push: bool,
+}
+
+// A backup as started from the command line, the daemon or its web page:
+// refused while a try is active, deferred to the push window when unattended,
+// then recorded, measured and reported to the healthcheck. Failure
+// notifications are only sent for unattended runs (cron, daemon), interactive
+// users see the error on their terminal.
+pub fn run(config: &Config, flags: &BackupFlags) -> io::Result<BackupReport> {
+    let unattended = flags.unattended;
+    log::init(config);
+    files::init(config)?;
+    let _lock = lock::write_lock()?;
+    log::info("backup started", &[("unattended", unattended.to_string())]);
+    // Backing up while trying another branch would commit the experiment.
+    let result = match trial::active()? {
+        Some(branch) => Err(io::Error::other(format!(
+            "not backing up while trying {}, run `syd try --end` first",
+            branch
+        ))),
+        None => {
+            // Outside the push windows unattended runs only commit; the
+            // queued commits go out with the first push inside a window.
+            let deferred = !flags.no_push && flags.unattended && !push::until_window(config)?.is_zero();
+            if deferred {
+                log::info("push deferred until the next push window", &[]);
+            }
+            backup_dotfiles(config, !flags.no_push && !deferred)
+        }
+    };
+    ops::record("backup", flags.trigger, result.as_ref().map(|report| report.files.as_slice()));
+    if let Err(e) = metrics::write_textfile(config, &result) {
+        log::warn(&format!("failed to write metrics: {}", e), &[]);
+    }
+    if let Err(e) = notifications::ping_healthcheck(config, &result) {
+        log::warn(&format!("failed to ping healthcheck: {}", e), &[]);
+    }
+    if let (true, Err(error)) = (unattended, &result) {
+        if let Err(e) = notifications::notify_failure(config, error) {
+            log::warn(&format!("failed to send notification: {}", e), &[]);
+        }
+    }
+    let report = result?;
+    log::info(
+        "backup finished",
+        &[
+            ("files_changed", report.files_changed.to_string()),
+            ("files_moved", report.files_moved.to_string()),
+            ("bytes_copied", report.bytes_copied.to_string()),
+            ("committed", report.committed.to_string()),
+            (
+                "push_seconds",
+                report.push_duration.map(|d| format!("{:.3}", d.as_secs_f64())).unwrap_or("null".to_string()),
+            ),
+        ],
+    );
+    Ok(report)
+}
+
+// Backs up every configured repo, stopping at the first one that fails.
+// Without `push` the backups are only committed locally.
+pub fn backup_dotfiles(config: &Config, push: bool) -> io::Result<BackupReport> {
+    let mut report = BackupReport::default();
+    for repo in config.repo_configs()? {
+        let repo_report = backup_repo(&repo, push)?;
+        report.files_changed += repo_report.files_changed;
+        report.files.extend(repo_report.files);
+        report.files_moved += repo_report.files_moved;
+        report.bytes_copied += repo_report.bytes_copied;
+        report.committed |= repo_report.committed;
+        if let Some(duration) = repo_report.push_duration {
+            report.push_duration = Some(report.push_duration.unwrap_or_default() + duration);
+        }
+    }
+    Ok(report)
+}
+
+fn backup_repo(config: &Config, push: bool) -> io::Result<BackupReport> {
+    let folder = config.backup_folder();
+    create_backup_folder(&folder)?;
+    let store = store::open(config)?;
+    store.init()?;
+
+    let mut report = BackupReport::default();
+    let mut cache = StatCache::load()?;
+    let mut mtimes = Mtimes::load(&folder)?;
+    for entry in &config.entries {
+        backup_entry(config, entry, &folder, &mut report, &mut cache, &mut mtimes)?;
+    }
+    mtimes.save()?;
+    report.files_moved = prune_moved(config, &folder)?;
+    manifest::write(config, &folder)?;
+
+    let host = git::hostname();
+    let moved = match report.files_moved {
+        0 => String::new(),
+        moved => format!(", {} moved", moved),
+    };
+    let message = format!(
+        "Backup from {}: {} file(s) changed{}\n\n{}: {}",
+        host,
+        report.files_changed,
+        moved,
+        metadata::HOST_TRAILER,
+        host
+    );
+    report.committed = store.commit(&message)?;
+    cache.save()?;
+
+    let start = Instant::now();
+    if push {
+        match store.push() {
+            Ok(true) => report.push_duration = Some(start.elapsed()),
+            Ok(false) => {}
+            Err(e) => {
+                let folder = json::string(&folder.to_string_lossy());
+                output::event("push_failed", &[("folder", folder), ("error", json::string(&e.to_string()))]);
+                return Err(e);
+            }
+        }
+    }
+    Ok(report)
+}
+
+// An entry dropped from the config whose file is gone from $HOME, while its
+// stored copy reappears byte for byte under a current entry, was moved. The
+// old copy is removed in the same commit, so git records a rename and
+// `git log --follow` keeps the history.
+fn prune_moved(config: &Config, folder: &Path) -> io::Result<usize> {
+    let Some(previous) = Manifest::load(folder)? else {
+        return Ok(0);
+    };
+    let mut stale = Vec::new();
+    for file in &previous.files {
+        if file.path.exists() || config.entries.iter().any(|entry| entry.path == file.path) {
+            continue;
+        }
+        let file = previous.resolve(file);
+        if storage::storage(&file) == Storage::Plain && file.options.str("submodule").is_none() {
+            stale.extend(storage::stored_files(config, &file, folder)?.into_iter().map(|(_, stored)| stored));
+        }
+    }
+    if stale.is_empty() {
+        return Ok(0);
+    }
+    let mut current = BTreeSet::new();
+    for entry in config.entries.iter().filter(|entry| storage::storage(entry) == Storage::Plain) {
+        for (_, stored) in storage::stored_files(config, entry, folder)? {
+            current.insert(hash::blob_id(&fs::read(&stored)?));
+        }
+    }
+    let mut moved = 0;
+    for stored in stale {
+        if !current.contains(&hash::blob_id(&fs::read(&stored)?)) {
+            continue;
+        }
+        fs::remove_file(&stored)?;
+        let mut parent = stored.parent();
+        while let Some(dir) = parent.filter(|dir| *dir != folder && dir.starts_with(folder)) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+        log::info(
+            "removed the old copy of a moved file",
+            &[("file", json::string(&stored.to_string_lossy()))],
+        );
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+fn backup_entry(
+    config: &Config,
+    entry: &Entry,
+    folder: &Path,
+    report: &mut BackupReport,
+    cache: &mut StatCache,
+    mtimes: &mut Mtimes,
+) -> io::Result<()> {
+    // Submodule entries are backed by their own upstream repo: syd only
+    // registers them and restores their files, it never copies into them.
+    if let Some(url) = entry.options.str("submodule") {
+        return git::add_submodule(folder, &entry.repo_path().to_string_lossy(), url);
+    }
+    if !entry.path.exists() {
+        log::warn(
+            &format!("skipping {}: file does not exist", entry.path.display()),
+            &[("path", json::string(&entry.path.to_string_lossy()))],
+        );
+        return Ok(());
+    }
+    let destination = folder.join(entry.repo_path());
+    let recipients = if entry.encrypted() {
+        crypto::recipients(folder)?
+    } else {
+        Vec::new()
+    };
+    let fingerprint = cache::fingerprint(config, entry);
+    let preserve_mtime = metadata::preserve_mtime(config, entry);
+    // Apps that chmod their config on every launch should not cause backups.
+    let ignore_metadata = entry.options.bool("ignore_metadata").unwrap_or(false);
+    let skip_open = editor::skip_open(config, entry)?;
+    for relative in owner::walk(owner::of(entry), &entry.path)? {
+        let source = files::join(&entry.path, &relative);
+        let target = files::join(&destination, &relative);
+        if cache.is_fresh(&source, &fingerprint) && storage::is_stored(config, entry, &target) {
+            continue;
+        }
+        // Left out of the cache, so the next backup tries again.
+        if let Some(marker) = editor::open_marker(&source).filter(|_| skip_open) {
+            log::warn(
+                &format!("skipping {}: open in an editor ({} exists)", source.display(), marker.display()),
+                &[("path", json::string(&source.to_string_lossy()))],
+            );
+            continue;
+        }
+        let content = filter::clean(entry, owner::read(owner::of(entry), &source)?)?;
+        let stored = storage::store(config, entry, &recipients, &source, &content, &target)?;
+        let changed = match stored {
+            Some(bytes) => {
+                report.bytes_copied += bytes;
+                true
+            }
+            None => !ignore_metadata && storage::update_mode(config, entry, &source, &target)?,
+        };
+        if changed {
+            output::event("backed_up", &[("path", json::string(&source.to_string_lossy()))]);
+            report.files_changed += 1;
+            report.files.push(source.clone());
+        }
+        // Only content changes move the recorded mtime, a mere touch does not.
+        let repo_file = files::join(&entry.repo_path(), &relative);
+        if preserve_mtime && (stored.is_some() || mtimes.get(&repo_file).is_none()) {
+            mtimes.set(&repo_file, fs::metadata(&source)?.modified()?);
+        }
+        cache.record(&source, &fingerprint);
+    }
+    Ok(())
+}
+
+fn create_backup_folder(backup_folder_path: &Path) -> io::Result<()> {
+    if !backup_folder_path.exists() {
+        fs::create_dir_all(backup_folder_path)?;
+    }
+    Ok(())
+}
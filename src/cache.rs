@@ -0,0 +1,72 @@
+// Size, modification time and mode of every file as of the last backup or
+// restore, so unchanged files are not read, filtered and compared again.
+use crate::config::{Config, Entry};
+use crate::hash;
+use crate::metadata;
+use crate::state;
+use crate::time;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+const CACHE_STATE: &str = "stat";
+// Files modified this recently may still change within the same mtime tick.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+pub struct StatCache {
+    stats: BTreeMap<String, String>,
+    changed: bool,
+}
+
+// Changing an entry's options, e.g. its filters, has to invalidate the cache.
+pub fn fingerprint(config: &Config, entry: &Entry) -> String {
+    let options = format!("{:?} {}", entry.options, metadata::preserve_mtime(config, entry));
+    hash::hex(&hash::sha1(options.as_bytes()))[..12].to_string()
+}
+
+fn stat(path: &Path, fingerprint: &str) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?;
+    if SystemTime::now().duration_since(mtime).unwrap_or_default() < RACY_WINDOW {
+        return None;
+    }
+    let mode = metadata.permissions().mode();
+    Some(format!("{} {} {:o} {}", metadata.len(), time::format_mtime(mtime), mode, fingerprint))
+}
+
+impl StatCache {
+    pub fn load() -> io::Result<StatCache> {
+        Ok(StatCache {
+            stats: state::load(CACHE_STATE)?,
+            changed: false,
+        })
+    }
+
+    pub fn is_fresh(&self, path: &Path, fingerprint: &str) -> bool {
+        let recorded = self.stats.get(&path.to_string_lossy().into_owned());
+        recorded.is_some() && recorded == stat(path, fingerprint).as_ref()
+    }
+
+    pub fn record(&mut self, path: &Path, fingerprint: &str) {
+        let key = path.to_string_lossy().into_owned();
+        let current = stat(path, fingerprint);
+        if self.stats.get(&key) == current.as_ref() {
+            return;
+        }
+        match current {
+            Some(current) => self.stats.insert(key, current),
+            None => self.stats.remove(&key),
+        };
+        self.changed = true;
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if self.changed {
+            state::save(CACHE_STATE, &self.stats)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,518 @@
+use crate::events;
+use crate::time;
+use simple_expand_tilde::*;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONFIG_PATH: &str = "~/.config/syd/";
+const CONFIG_FILE: &str = "syd.conf";
+const DEFAULT_BACKUP_FOLDER: &str = "~/syd/";
+const DEFAULT_BRANCH: &str = "main";
+pub const DEFAULT_REPO: &str = "default";
+// `[[repos]]` keys that end up under `backup.`, see `store`.
+const VCS_KEYS: [&str; 6] = ["vcs", "vcs_init", "vcs_pull", "vcs_status", "vcs_commit", "vcs_push"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Str(String),
+    Bool(bool),
+    Int(i64),
+    List(Vec<String>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Table(BTreeMap<String, Value>);
+
+impl Table {
+    pub fn insert(&mut self, key: String, value: Value) {
+        self.0.insert(key, value);
+    }
+
+    pub fn str(&self, key: &str) -> Option<&str> {
+        match self.0.get(key) {
+            Some(Value::Str(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, key: &str) -> Option<bool> {
+        match self.0.get(key) {
+            Some(Value::Bool(b)) => Some(*b),
+            Some(Value::Str(s)) => match s.as_str() {
+                "true" | "yes" | "on" => Some(true),
+                "false" | "no" | "off" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn int(&self, key: &str) -> Option<i64> {
+        match self.0.get(key) {
+            Some(Value::Int(i)) => Some(*i),
+            Some(Value::Str(s)) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self, key: &str) -> Vec<String> {
+        match self.0.get(key) {
+            Some(Value::List(items)) => items.clone(),
+            Some(Value::Str(s)) => vec![s.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn duration(&self, key: &str) -> Option<Duration> {
+        match self.0.get(key) {
+            Some(Value::Int(seconds)) => u64::try_from(*seconds).ok().map(Duration::from_secs),
+            Some(Value::Str(s)) => time::parse_duration(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub options: Table,
+}
+
+impl Entry {
+    // Files below $HOME keep their relative path inside the backup folder,
+    // everything else is stored under `_root/` with its absolute path. A
+    // manifest from the backup repo may record a different `repo_path`.
+    pub fn repo_path(&self) -> PathBuf {
+        match self.options.str("repo_path") {
+            Some(repo_path) => PathBuf::from(repo_path),
+            None => repo_path_for(&self.path),
+        }
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.options.bool("encrypt").unwrap_or(false)
+    }
+
+    pub fn repo(&self) -> Option<&str> {
+        self.options.str("repo")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    pub settings: Table,
+    pub entries: Vec<Entry>,
+    pub repos: Vec<Table>,
+}
+
+impl Config {
+    pub fn backup_folder(&self) -> PathBuf {
+        let folder = self.settings.str("backup.folder").unwrap_or(DEFAULT_BACKUP_FOLDER);
+        expand_path(folder)
+    }
+
+    pub fn remote(&self) -> Option<&str> {
+        self.settings.str("git.remote").filter(|r| !r.is_empty())
+    }
+
+    pub fn branch(&self) -> &str {
+        self.settings.str("git.branch").unwrap_or(DEFAULT_BRANCH)
+    }
+
+    // Splits the config into one config per backup repo. Entries without a
+    // `repo` option stay in the default repo described by `backup.folder` and
+    // `[git]`; each `[[repos]]` table brings its own folder, remote, branch
+    // and vcs and inherits every other setting.
+    pub fn repo_configs(&self) -> io::Result<Vec<Config>> {
+        let mut configs = Vec::new();
+        let default_entries: Vec<Entry> = self.entries.iter().filter(|e| e.repo().is_none()).cloned().collect();
+        if self.repos.is_empty() || !default_entries.is_empty() {
+            configs.push(Config {
+                path: self.path.clone(),
+                settings: self.settings.clone(),
+                entries: default_entries,
+                repos: Vec::new(),
+            });
+        }
+        for repo in &self.repos {
+            let name = repo
+                .str("name")
+                .ok_or_else(|| io::Error::other("[[repos]] entry without a name"))?;
+            let mut settings = self.settings.clone();
+            let folder = repo.str("folder").map(String::from).unwrap_or(format!("~/syd-{}", name));
+            settings.insert("backup.folder".to_string(), Value::Str(folder));
+            settings.0.remove("git.remote");
+            if let Some(remote) = repo.str("remote") {
+                settings.insert("git.remote".to_string(), Value::Str(remote.to_string()));
+            }
+            let branch = repo.str("branch").unwrap_or(DEFAULT_BRANCH);
+            settings.insert("git.branch".to_string(), Value::Str(branch.to_string()));
+            for key in VCS_KEYS {
+                let name = format!("backup.{}", key);
+                settings.0.remove(&name);
+                if let Some(value) = repo.str(key) {
+                    settings.insert(name, Value::Str(value.to_string()));
+                }
+            }
+            configs.push(Config {
+                path: self.path.clone(),
+                settings,
+                entries: self.entries.iter().filter(|e| e.repo() == Some(name)).cloned().collect(),
+                repos: Vec::new(),
+            });
+        }
+        if let Some(entry) = self
+            .entries
+            .iter()
+            .find(|e| e.repo().is_some_and(|name| !self.repos.iter().any(|r| r.str("name") == Some(name))))
+        {
+            return Err(io::Error::other(format!(
+                "{}: unknown repo {:?}",
+                entry.path.display(),
+                entry.repo().unwrap_or_default()
+            )));
+        }
+        Ok(configs)
+    }
+
+    // Narrows the config down to one repo, `default` being the one described
+    // by `backup.folder` and `[git]`.
+    pub fn select_repo(&self, name: &str) -> io::Result<Config> {
+        let mut selected = self.clone();
+        if name == DEFAULT_REPO {
+            selected.entries.retain(|entry| entry.repo().is_none());
+            selected.repos.clear();
+            return Ok(selected);
+        }
+        if !self.repos.iter().any(|repo| repo.str("name") == Some(name)) {
+            return Err(io::Error::other(format!("unknown repo {:?}", name)));
+        }
+        selected.entries.retain(|entry| entry.repo() == Some(name));
+        selected.repos.retain(|repo| repo.str("name") == Some(name));
+        Ok(selected)
+    }
+}
+
+pub fn home_dir() -> PathBuf {
+    expand_tilde("~").expect("Failed to determine home directory")
+}
+
+pub fn expand_path(path: &str) -> PathBuf {
+    let path = expand_tilde(path).unwrap_or_else(|| PathBuf::from(path));
+    if path.is_absolute() {
+        path
+    } else {
+        home_dir().join(path)
+    }
+}
+
+pub fn display_path(path: &Path) -> String {
+    match path.strip_prefix(home_dir()) {
+        Ok(relative) => Path::new("~").join(relative).display().to_string(),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+pub fn repo_path_for(path: &Path) -> PathBuf {
+    match path.strip_prefix(home_dir()) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => Path::new("_root").join(path.strip_prefix("/").unwrap_or(path)),
+    }
+}
+
+pub fn read_config_path(config_path: String, config_file: String) -> PathBuf {
+    let mut config = expand_tilde(config_path).expect("Failed to expand tilde into config path");
+    config.push(PathBuf::from(config_file));
+    config
+}
+
+pub fn load() -> io::Result<Config> {
+    let path = match env::var_os("SYD_CONFIG") {
+        Some(path) => PathBuf::from(path),
+        None => read_config_path(CONFIG_PATH.to_string(), CONFIG_FILE.to_string()),
+    };
+    let config = read_config(path)?;
+    time::init(config.settings.str("clock.now"))?;
+    events::configure(config.settings.str("events.path"));
+    Ok(config)
+}
+
+pub fn read_config(path: PathBuf) -> io::Result<Config> {
+    let text = fs::read_to_string(&path).map_err(|e| {
+        io::Error::new(e.kind(), format!("Could not read config {}: {}", path.display(), e))
+    })?;
+    let (settings, entries, repos) = parse(&text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+    Ok(Config {
+        path,
+        settings,
+        entries,
+        repos,
+    })
+}
+
+// New entries go at the end of the bare path list, before the first section.
+pub fn add_entries(config: &Config, paths: &[PathBuf]) -> io::Result<()> {
+    let text = fs::read_to_string(&config.path)?;
+    let mut lines: Vec<&str> = text.lines().collect();
+    let mut insert_at = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    while insert_at > 0 && lines[insert_at - 1].trim().is_empty() {
+        insert_at -= 1;
+    }
+    let added: Vec<String> = paths.iter().map(|path| display_path(path)).collect();
+    for (offset, line) in added.iter().enumerate() {
+        lines.insert(insert_at + offset, line);
+    }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    fs::write(&config.path, text)
+}
+
+// Dotfiles in $HOME and entries of ~/.config that are not tracked yet.
+pub fn untracked_candidates(config: &Config) -> io::Result<Vec<PathBuf>> {
+    const NOISE: &[&str] = &[
+        ".cache", ".local", ".git", ".npm", ".cargo", ".rustup", ".mozilla", ".Trash", ".ssh", ".gnupg",
+        ".pki", ".dbus", ".bash_history", ".zsh_history", ".python_history", ".lesshst", ".viminfo",
+        ".wget-hsts", ".Xauthority", ".ICEauthority", ".xsession-errors", ".sudo_as_admin_successful",
+    ];
+    let home = home_dir();
+    let backup_folder = config.backup_folder();
+    let mut candidates = Vec::new();
+    let mut scan = |dir: &Path, dotted_only: bool| -> io::Result<()> {
+        let Ok(children) = fs::read_dir(dir) else {
+            return Ok(());
+        };
+        for child in children {
+            let child = child?;
+            let name = child.file_name().to_string_lossy().into_owned();
+            if (dotted_only && !name.starts_with('.')) || NOISE.contains(&name.as_str()) || name == ".config" {
+                continue;
+            }
+            candidates.push(child.path());
+        }
+        Ok(())
+    };
+    scan(&home, true)?;
+    scan(&home.join(".config"), false)?;
+    candidates.retain(|path| {
+        !path.starts_with(&backup_folder)
+            && !config
+                .entries
+                .iter()
+                .any(|entry| path.starts_with(&entry.path) || entry.path.starts_with(path))
+    });
+    candidates.sort();
+    Ok(candidates)
+}
+
+// The config is a small TOML subset. Bare lines before the first section
+// are treated as paths to back up, which keeps plain lists of files valid.
+pub fn parse(text: &str) -> Result<(Table, Vec<Entry>, Vec<Table>), String> {
+    let mut settings = Table::default();
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut pending: Vec<Table> = Vec::new();
+    let mut repos: Vec<Table> = Vec::new();
+    let mut section = String::new();
+    // The `[[files]]` or `[[repos]]` table the following keys belong to.
+    let mut table_array: Option<&str> = None;
+    let mut lines = text.lines().enumerate();
+
+    while let Some((number, raw)) = lines.next() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("[[").and_then(|l| l.strip_suffix("]]")) {
+            let name = name.trim();
+            match name {
+                "files" => pending.push(Table::default()),
+                "repos" => repos.push(Table::default()),
+                _ => return Err(format!("line {}: unknown table array [[{}]]", number + 1, name)),
+            }
+            table_array = Some(name);
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            table_array = None;
+            continue;
+        }
+        let Some((key, value)) = split_key_value(line) else {
+            if section.is_empty() && table_array.is_none() {
+                entries.push(Entry {
+                    path: expand_path(line),
+                    options: Table::default(),
+                });
+                continue;
+            }
+            return Err(format!("line {}: expected `key = value`", number + 1));
+        };
+        let mut value = strip_comment(value).to_string();
+        while value.starts_with('[') && !list_closed(&value) {
+            match lines.next() {
+                Some((_, next)) => {
+                    value.push(' ');
+                    value.push_str(strip_comment(next.trim()));
+                }
+                None => return Err(format!("line {}: unterminated list", number + 1)),
+            }
+        }
+        let value = parse_value(&value).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        if let Some(array) = table_array {
+            let table = if array == "files" { pending.last_mut() } else { repos.last_mut() };
+            table.expect("table array").insert(key.to_string(), value);
+        } else if section.is_empty() {
+            settings.insert(key.to_string(), value);
+        } else {
+            settings.insert(format!("{}.{}", section, key), value);
+        }
+    }
+
+    for (index, options) in pending.into_iter().enumerate() {
+        let path = options
+            .str("path")
+            .map(expand_path)
+            .ok_or_else(|| format!("[[files]] entry {} has no path", index + 1))?;
+        entries.push(Entry {
+            path,
+            options,
+        });
+    }
+    // A repo's `files` list is shorthand for entries with `repo = <name>`.
+    for repo in &repos {
+        let (Some(name), files) = (repo.str("name"), repo.list("files")) else {
+            continue;
+        };
+        for file in files {
+            let mut options = Table::default();
+            options.insert("repo".to_string(), Value::Str(name.to_string()));
+            entries.push(Entry {
+                path: expand_path(&file),
+                options,
+            });
+        }
+    }
+    Ok((settings, entries, repos))
+}
+
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    valid.then(|| (key, value.trim()))
+}
+
+fn list_closed(value: &str) -> bool {
+    let mut quote = None;
+    for c in value.chars() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ']') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn strip_comment(value: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in value.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') => return value[..index].trim_end(),
+            _ => {}
+        }
+    }
+    value
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        let mut items = Vec::new();
+        for item in split_list(inner) {
+            match parse_value(&item)? {
+                Value::Str(s) => items.push(s),
+                Value::Int(i) => items.push(i.to_string()),
+                Value::Bool(b) => items.push(b.to_string()),
+                Value::List(_) => return Err("nested lists are not supported".to_string()),
+            }
+        }
+        return Ok(Value::List(items));
+    }
+    if let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        return unescape(inner).map(Value::Str);
+    }
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return Ok(Value::Str(inner.to_string()));
+    }
+    match value {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => match value.parse() {
+            Ok(i) => Ok(Value::Int(i)),
+            Err(_) => Ok(Value::Str(value.to_string())),
+        },
+    }
+}
+
+fn split_list(inner: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in inner.chars() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                current.push(c);
+                continue;
+            }
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, ',') => {
+                items.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        escaped = false;
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        items.push(current.trim().to_string());
+    }
+    items
+}
+
+fn unescape(value: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            other => return Err(format!("invalid escape \\{}", other.map(String::from).unwrap_or_default())),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,108 @@
+// When local backups cannot be replayed on top of the remote, CONFLICTS.md in
+// the state directory lists each conflicted file with both versions and the
+// commands to settle it by hand.
+use crate::config;
+use crate::git::{self, Conflict};
+use crate::json;
+use crate::log;
+use crate::output;
+use crate::state;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const REPORT_FILE: &str = "CONFLICTS.md";
+
+fn report_path() -> io::Result<PathBuf> {
+    Ok(state::state_dir()?.join(REPORT_FILE))
+}
+
+// `git::sync_with_remote`, writing the report when it hits conflicts.
+pub fn sync_with_remote(folder: &Path, branch: &str) -> io::Result<()> {
+    match git::sync_with_remote(folder, branch) {
+        Ok(()) => clear_report(),
+        Err(mut error) => {
+            if let Some(conflict) = error.get_mut().and_then(|e| e.downcast_mut::<Conflict>()) {
+                if let Err(e) = write_report(folder, branch, conflict) {
+                    log::warn(&format!("failed to write the conflict report: {}", e), &[]);
+                }
+                let files: Vec<String> = conflict.files.iter().map(|file| json::string(file)).collect();
+                let report = match &conflict.report {
+                    Some(report) => json::string(&report.to_string_lossy()),
+                    None => "null".to_string(),
+                };
+                output::event(
+                    "conflict",
+                    &[
+                        ("folder", json::string(&folder.to_string_lossy())),
+                        ("files", json::array(&files)),
+                        ("report", report),
+                    ],
+                );
+            }
+            Err(error)
+        }
+    }
+}
+
+// Writes the report for `conflict` and points the conflict at it.
+fn write_report(folder: &Path, branch: &str, conflict: &mut Conflict) -> io::Result<()> {
+    let upstream = format!("origin/{}", branch);
+    let repo = config::display_path(folder);
+    let mut text = format!(
+        "# Backup conflicts\n\n\
+         The backups made on {} could not be replayed on top of `{}` in `{}`\n\
+         because both sides changed the files below. Nothing has been changed\n\
+         yet; the repo is back where it was before the backup tried to sync.\n",
+        git::hostname(),
+        upstream,
+        repo
+    );
+    for file in &conflict.files {
+        text.push_str(&format!(
+            "\n## {}\n\n- this machine: {}\n- remote: {}\n",
+            file,
+            version(folder, "HEAD", file),
+            version(folder, &upstream, file)
+        ));
+    }
+    text.push_str(&format!(
+        "\n## Resolving\n\n\
+         Start replaying the local backups again, which stops at the conflicts:\n\n\
+         \x20   git -C {repo} rebase {upstream}\n\n\
+         For each file above, either edit it in `{repo}` to combine both versions,\n\
+         or keep one side (during a rebase `--ours` is the remote, `--theirs`\n\
+         is this machine):\n\n\
+         \x20   git -C {repo} checkout --ours -- FILE     # keep the remote version\n\
+         \x20   git -C {repo} checkout --theirs -- FILE   # keep this machine's version\n\
+         \x20   git -C {repo} add -- FILE\n\n\
+         Then finish and push with:\n\n\
+         \x20   git -C {repo} rebase --continue\n\
+         \x20   syd backup\n\n\
+         `git -C {repo} rebase --abort` gives up and leaves the repo as it is now.\n",
+        repo = repo,
+        upstream = upstream
+    ));
+    let path = report_path()?;
+    fs::write(&path, text)?;
+    conflict.report = Some(path);
+    Ok(())
+}
+
+// Drops a report left from an earlier conflict once syncing works again.
+fn clear_report() -> io::Result<()> {
+    match fs::remove_file(report_path()?) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// The blob and the last commit that changed `file` as of `revision`.
+fn version(folder: &Path, revision: &str, file: &str) -> String {
+    let blob = git::git(folder, &["rev-parse", "--short", &format!("{}:{}", revision, file)]);
+    let commit = git::git(folder, &["log", "-1", "--format=%h, %cI, %s", revision, "--", file]);
+    match (blob, commit) {
+        (Ok(blob), Ok(commit)) => format!("blob `{}` (commit {})", blob, commit),
+        _ => "deleted".to_string(),
+    }
+}
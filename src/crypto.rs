@@ -0,0 +1,220 @@
+use crate::config::{expand_path, Config, Entry};
+use crate::exec;
+use crate::files;
+use crate::hash;
+use crate::manifest::Manifest;
+use crate::recovery;
+use crate::state;
+use crate::storage::{self, Storage};
+use crate::store;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+// The recipient list lives in the backup repo, so everybody sharing the repo
+// encrypts for the same set of people.
+pub const RECIPIENTS_FILE: &str = ".syd-recipients";
+const CACHE_STATE: &str = "encrypted";
+
+// The recovery public keys imported by this process, with their contents.
+static IMPORTED: Mutex<Vec<(PathBuf, Vec<u8>)>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Tool {
+    Gpg,
+    Age,
+}
+
+pub fn tool(config: &Config) -> io::Result<Tool> {
+    match config.settings.str("encryption.tool").unwrap_or("gpg") {
+        "gpg" => Ok(Tool::Gpg),
+        "age" => Ok(Tool::Age),
+        other => Err(io::Error::other(format!("unsupported encryption.tool {:?}", other))),
+    }
+}
+
+pub fn extension(config: &Config) -> &'static str {
+    match tool(config) {
+        Ok(Tool::Age) => "age",
+        _ => "gpg",
+    }
+}
+
+pub fn encrypted_name(config: &Config, path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(extension(config));
+    PathBuf::from(name)
+}
+
+pub fn recipients(folder: &Path) -> io::Result<Vec<String>> {
+    match fs::read_to_string(folder.join(RECIPIENTS_FILE)) {
+        Ok(text) => Ok(text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn save_recipients(folder: &Path, recipients: &[String]) -> io::Result<()> {
+    let mut text = String::from("# Recipients every encrypted file in this repo is encrypted for.\n");
+    for recipient in recipients {
+        text.push_str(recipient);
+        text.push('\n');
+    }
+    files::replace(&folder.join(RECIPIENTS_FILE), text.as_bytes())
+}
+
+pub fn encrypt(config: &Config, recipients: &[String], plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    if recipients.is_empty() {
+        return Err(io::Error::other(
+            "no encryption recipients configured, run `syd keys add <recipient>` first",
+        ));
+    }
+    let mut command = match tool(config)? {
+        Tool::Gpg => {
+            import_recovery_key(config)?;
+            let mut command = Command::new("gpg");
+            command.args(["--batch", "--yes", "--quiet", "--trust-model", "always", "--encrypt"]);
+            for recipient in recipients {
+                command.args(["--recipient", recipient]);
+            }
+            command
+        }
+        Tool::Age => {
+            let mut command = Command::new("age");
+            command.arg("--encrypt");
+            for recipient in recipients {
+                command.args(["--recipient", recipient]);
+            }
+            command
+        }
+    };
+    command.args(["--output", "-"]);
+    exec::pipe(command, plaintext)
+}
+
+// A gpg recovery recipient added on another machine is only in that machine's
+// keyring, so its public key, committed next to the recipients, is imported
+// before the first file is encrypted for it, and again when it changes.
+fn import_recovery_key(config: &Config) -> io::Result<()> {
+    let path = config.backup_folder().join(recovery::PUBLIC_KEY_FILE);
+    let Ok(key) = fs::read(&path) else {
+        return Ok(());
+    };
+    let mut imported = IMPORTED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if imported.iter().any(|(known, known_key)| *known == path && *known_key == key) {
+        return Ok(());
+    }
+    let mut command = Command::new("gpg");
+    command.args(["--batch", "--quiet", "--import"]);
+    exec::pipe(command, &key).map_err(|e| {
+        io::Error::other(format!(
+            "could not import the recovery key, run `gpg --import {}`: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    imported.retain(|(known, _)| *known != path);
+    imported.push((path, key));
+    Ok(())
+}
+
+pub fn decrypt(config: &Config, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+    let command = match tool(config)? {
+        Tool::Gpg => {
+            let mut command = Command::new("gpg");
+            command.args(["--batch", "--quiet", "--decrypt"]);
+            command
+        }
+        Tool::Age => {
+            let identity = config.settings.str("encryption.identity").unwrap_or("~/.config/age/keys.txt");
+            let mut command = Command::new("age");
+            command.arg("--decrypt").arg("--identity").arg(expand_path(identity));
+            command
+        }
+    };
+    exec::pipe(command, ciphertext)
+}
+
+// Ciphertexts differ on every run, so whether an encrypted copy is current
+// is decided from a local record of what was last encrypted into it.
+pub fn is_current(repo_file: &Path, plaintext: &[u8]) -> io::Result<bool> {
+    let Ok(ciphertext) = fs::read(repo_file) else {
+        return Ok(false);
+    };
+    let cache = state::load(CACHE_STATE)?;
+    let expected = format!("{} {}", hash::blob_id(plaintext), hash::blob_id(&ciphertext));
+    Ok(cache.get(&repo_file.to_string_lossy().into_owned()) == Some(&expected))
+}
+
+pub fn write_encrypted(config: &Config, recipients: &[String], repo_file: &Path, plaintext: &[u8]) -> io::Result<u64> {
+    let ciphertext = encrypt(config, recipients, plaintext)?;
+    if let Some(parent) = repo_file.parent() {
+        files::create_dirs(parent)?;
+    }
+    files::replace(repo_file, &ciphertext)?;
+    let mut cache = state::load(CACHE_STATE)?;
+    cache.insert(
+        repo_file.to_string_lossy().into_owned(),
+        format!("{} {}", hash::blob_id(plaintext), hash::blob_id(&ciphertext)),
+    );
+    state::save(CACHE_STATE, &cache)?;
+    Ok(ciphertext.len() as u64)
+}
+
+// Stores the new recipient list and re-encrypts everything for it in a
+// single commit, which is pushed right away so the whole team picks it up.
+pub fn change_recipients(config: &Config, recipients: &[String], message: &str) -> io::Result<usize> {
+    let folder = config.backup_folder();
+    fs::create_dir_all(&folder)?;
+    let store = store::open(config)?;
+    store.init()?;
+    let rewritten = reencrypt_all(config, recipients)?;
+    save_recipients(&folder, recipients)?;
+    store.commit(message)?;
+    store.push()?;
+    Ok(rewritten.len())
+}
+
+// Decrypts and re-encrypts the stored files of every encrypted entry, the
+// configured ones and those other machines recorded in the manifest, for the
+// given recipients, returning the files that were rewritten. Other files that
+// happen to end in `.gpg` or `.age`, such as plainly tracked keys, are left
+// alone.
+pub fn reencrypt_all(config: &Config, recipients: &[String]) -> io::Result<Vec<PathBuf>> {
+    let folder = config.backup_folder();
+    let manifest = Manifest::load(&folder)?;
+    let mut entries: Vec<Entry> = config
+        .entries
+        .iter()
+        .map(|entry| manifest.as_ref().map_or_else(|| entry.clone(), |manifest| manifest.resolve(entry)))
+        .collect();
+    if let Some(manifest) = &manifest {
+        for file in &manifest.files {
+            if !config.entries.iter().any(|entry| entry.path == file.path) {
+                entries.push(manifest.resolve(file));
+            }
+        }
+    }
+    let mut rewritten = Vec::new();
+    for entry in entries.iter().filter(|entry| storage::storage(entry) == Storage::Encrypted) {
+        for (_, repo_file) in storage::stored_files(config, entry, &folder)? {
+            let relative = repo_file.strip_prefix(&folder).unwrap_or(&repo_file).to_path_buf();
+            if rewritten.contains(&relative) {
+                continue;
+            }
+            let plaintext = decrypt(config, &fs::read(&repo_file)?)
+                .map_err(|e| io::Error::other(format!("{}: {}", relative.display(), e)))?;
+            write_encrypted(config, recipients, &repo_file, &plaintext)?;
+            rewritten.push(relative);
+        }
+    }
+    Ok(rewritten)
+}
@@ -0,0 +1,181 @@
+// Binary deltas for large files that change a little at a time. A delta is a
+// list of copy (from the base) and insert (literal bytes) instructions.
+use std::collections::HashMap;
+use std::io;
+
+const MAGIC: &[u8] = b"SYDDELTA1\n";
+const BLOCK: usize = 16;
+const COPY: u8 = 1;
+const INSERT: u8 = 2;
+
+pub fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        index.entry(&base[offset..offset + BLOCK]).or_insert(offset);
+    }
+
+    let mut out = MAGIC.to_vec();
+    let mut literal_start = 0;
+    let mut position = 0;
+    while position + BLOCK <= target.len() {
+        let Some(&start) = index.get(&target[position..position + BLOCK]) else {
+            position += 1;
+            continue;
+        };
+        // Grow the match in both directions as far as the bytes agree.
+        let mut base_start = start;
+        let mut target_start = position;
+        while base_start > 0 && target_start > literal_start && base[base_start - 1] == target[target_start - 1] {
+            base_start -= 1;
+            target_start -= 1;
+        }
+        let mut length = position - target_start + BLOCK;
+        while base_start + length < base.len()
+            && target_start + length < target.len()
+            && base[base_start + length] == target[target_start + length]
+        {
+            length += 1;
+        }
+        push_insert(&mut out, &target[literal_start..target_start]);
+        out.push(COPY);
+        push_varint(&mut out, base_start as u64);
+        push_varint(&mut out, length as u64);
+        position = target_start + length;
+        literal_start = position;
+    }
+    push_insert(&mut out, &target[literal_start..]);
+    out
+}
+
+pub fn apply(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt delta");
+    let mut rest = delta.strip_prefix(MAGIC).ok_or_else(corrupt)?;
+    let mut out = Vec::new();
+    while let Some((&op, tail)) = rest.split_first() {
+        rest = tail;
+        match op {
+            COPY => {
+                let offset = read_varint(&mut rest).ok_or_else(corrupt)? as usize;
+                let length = read_varint(&mut rest).ok_or_else(corrupt)? as usize;
+                let end = offset.checked_add(length).filter(|end| *end <= base.len()).ok_or_else(corrupt)?;
+                out.extend_from_slice(&base[offset..end]);
+            }
+            INSERT => {
+                let length = read_varint(&mut rest).ok_or_else(corrupt)? as usize;
+                if length > rest.len() {
+                    return Err(corrupt());
+                }
+                out.extend_from_slice(&rest[..length]);
+                rest = &rest[length..];
+            }
+            _ => return Err(corrupt()),
+        }
+    }
+    Ok(out)
+}
+
+fn push_insert(out: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    out.push(INSERT);
+    push_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(count: u32) -> Vec<u8> {
+        (0..count).flat_map(|n| format!("line {}\n", n).into_bytes()).collect()
+    }
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let delta = diff(base, target);
+        assert_eq!(apply(base, &delta).unwrap(), target);
+        delta
+    }
+
+    #[test]
+    fn small_edits_produce_small_deltas() {
+        let base = lines(1000);
+        let mut target = base.clone();
+        target.splice(5000..5004, b"edited".iter().copied());
+        target.extend_from_slice(b"appended\n");
+        target.drain(..7);
+        let delta = round_trip(&base, &target);
+        assert!(delta.len() < 100, "{} byte delta", delta.len());
+    }
+
+    #[test]
+    fn handles_empty_and_unrelated_inputs() {
+        round_trip(b"", b"");
+        round_trip(b"", b"new file");
+        round_trip(&lines(10), b"");
+        round_trip(b"short", b"short");
+        round_trip(&lines(100), &[0xffu8; 3000]);
+        // Instructions carry lengths and offsets past one varint byte.
+        round_trip(&lines(20000), &lines(20000)[70000..]);
+    }
+
+    #[test]
+    fn identical_files_are_one_copy() {
+        let base = lines(100);
+        let delta = diff(&base, &base);
+        let mut expected = MAGIC.to_vec();
+        expected.push(COPY);
+        push_varint(&mut expected, 0);
+        push_varint(&mut expected, base.len() as u64);
+        assert_eq!(delta, expected);
+    }
+
+    #[test]
+    fn rejects_corrupt_deltas() {
+        let base = lines(10);
+        let mut target = base[20..60].to_vec();
+        target.extend_from_slice(b"something else");
+        let delta = diff(&base, &target);
+        assert!(apply(&base, &delta[1..]).is_err());
+        assert!(apply(&base, &delta[..delta.len() - 1]).is_err());
+        assert!(apply(&base[..10], &delta).is_err());
+        let mut bad = MAGIC.to_vec();
+        bad.push(7);
+        assert!(apply(&base, &bad).is_err());
+        let mut unterminated = MAGIC.to_vec();
+        unterminated.extend_from_slice(&[COPY, 0x80, 0x80]);
+        assert!(apply(&base, &unterminated).is_err());
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            push_varint(&mut out, value);
+            let mut input = out.as_slice();
+            assert_eq!(read_varint(&mut input), Some(value));
+            assert!(input.is_empty());
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// `syd doctor`: the health of each backup repo and its remote. It checks that
+// the remote answers, and compares the size of the repo, local and as the
+// forge reports it, with the forge's limit (see `forge::remote_size`). With
+// `push.quota_check` the same size check runs before pushes of more than
+// `push.quota_check_above` bytes (10 MiB by default), so a backup heading into
+// a limit gets a warning before the forge starts refusing it.
+use crate::config::Config;
+use crate::exec;
+use crate::files;
+use crate::forge;
+use crate::git;
+use crate::log;
+use crate::store;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_WARN_PERCENT: i64 = 80;
+const DEFAULT_CHECK_ABOVE: i64 = 10 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Ok,
+    Warning,
+    // The check could not run, e.g. because the forge did not answer.
+    Unknown,
+}
+
+impl Level {
+    pub fn label(self) -> &'static str {
+        match self {
+            Level::Ok => "ok",
+            Level::Warning => "warning",
+            Level::Unknown => "unknown",
+        }
+    }
+}
+
+pub struct Finding {
+    pub folder: PathBuf,
+    pub level: Level,
+    pub message: String,
+}
+
+pub fn doctor(config: &Config) -> io::Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    for repo in config.repo_configs()? {
+        let folder = repo.backup_folder();
+        let mut found = |level, message: String| {
+            findings.push(Finding {
+                folder: folder.clone(),
+                level,
+                message,
+            })
+        };
+        if !store::is_git(&repo) {
+            found(Level::Unknown, "not a git repo, nothing to check".to_string());
+            continue;
+        }
+        if !folder.join(".git").exists() {
+            found(Level::Unknown, "not backed up yet".to_string());
+            continue;
+        }
+        let local = local_size(&folder)?;
+        let Some(remote) = repo.remote() else {
+            found(Level::Ok, format!("{} locally, no remote", files::format_size(local)));
+            continue;
+        };
+        match git::git(&folder, &["ls-remote", "--heads", "origin", repo.branch()]) {
+            Ok(heads) if heads.trim().is_empty() => {
+                found(Level::Warning, format!("{} has no branch {} yet", remote, repo.branch()))
+            }
+            Ok(_) => found(Level::Ok, format!("{} answers", remote)),
+            Err(e) => found(Level::Warning, format!("{} does not answer: {}", remote, e)),
+        }
+        match forge::remote_size(&repo) {
+            Ok(size) => {
+                let level = match quota_warning(&repo, size.bytes.max(local), size.limit) {
+                    Some(_) => Level::Warning,
+                    None => Level::Ok,
+                };
+                found(level, describe(&repo, local, &size));
+            }
+            Err(e) => found(
+                Level::Unknown,
+                format!("{} locally, remote size unknown: {}", files::format_size(local), e),
+            ),
+        }
+    }
+    Ok(findings)
+}
+
+// Best effort: a failed check never stops the push.
+pub fn check_before_push(config: &Config, folder: &Path, branch: &str) {
+    if !config.settings.bool("push.quota_check").unwrap_or(false) {
+        return;
+    }
+    let above = config.settings.int("push.quota_check_above").unwrap_or(DEFAULT_CHECK_ABOVE);
+    let outgoing = match outgoing_size(folder, branch) {
+        Ok(outgoing) => outgoing,
+        Err(e) => {
+            log::warn(&format!("could not measure the push for the quota check: {}", e), &[]);
+            return;
+        }
+    };
+    if (outgoing as i64) < above {
+        return;
+    }
+    match forge::remote_size(config) {
+        Ok(size) => {
+            if let Some(warning) = quota_warning(config, size.bytes + outgoing, size.limit) {
+                log::warn(
+                    &format!("pushing {} more: {}", files::format_size(outgoing), warning),
+                    &[("outgoing", outgoing.to_string()), ("remote_size", size.bytes.to_string())],
+                );
+            }
+        }
+        Err(e) => log::warn(&format!("could not check the remote's quota: {}", e), &[]),
+    }
+}
+
+fn describe(config: &Config, local: u64, size: &forge::RemoteSize) -> String {
+    let sizes = format!(
+        "{} locally, {} on the forge",
+        files::format_size(local),
+        files::format_size(size.bytes)
+    );
+    match (quota_warning(config, size.bytes.max(local), size.limit), size.limit) {
+        (Some(warning), _) => format!("{}: {}", sizes, warning),
+        (None, Some(limit)) => format!("{}, limit {}", sizes, files::format_size(limit)),
+        (None, None) => format!("{}, no known limit", sizes),
+    }
+}
+
+// Set when `size` reaches `push.quota_warn` percent of the limit.
+fn quota_warning(config: &Config, size: u64, limit: Option<u64>) -> Option<String> {
+    let limit = limit?;
+    let percent = config.settings.int("push.quota_warn").unwrap_or(DEFAULT_WARN_PERCENT).clamp(1, 100) as u64;
+    if size.saturating_mul(100) < limit.saturating_mul(percent) {
+        return None;
+    }
+    Some(format!(
+        "{}% of the {} limit; move large files to Git LFS or prune their history (see `syd stats --repo`)",
+        size.saturating_mul(100) / limit.max(1),
+        files::format_size(limit)
+    ))
+}
+
+fn local_size(folder: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for line in git::git(folder, &["count-objects", "-v"])?.lines() {
+        if let Some(("size" | "size-pack", kib)) = line.split_once(": ") {
+            size += kib.trim().parse::<u64>().unwrap_or(0) * 1024;
+        }
+    }
+    Ok(size)
+}
+
+// The compressed size of the objects the remote does not have yet.
+fn outgoing_size(folder: &Path, branch: &str) -> io::Result<u64> {
+    let upstream = format!("origin/{}", branch);
+    let range = format!("{}..HEAD", upstream);
+    let known = git::succeeds(folder, &["rev-parse", "--verify", "--quiet", &upstream])?;
+    let objects = git::git(folder, &["rev-list", "--objects", if known { &range } else { "HEAD" }])?;
+    let mut command = git::command(folder);
+    // %(rest) makes git split the path off the object name.
+    command.args(["cat-file", "--batch-check=%(objectsize:disk) %(rest)"]);
+    let output = exec::pipe(command, format!("{}\n", objects).as_bytes())?;
+    let sizes = String::from_utf8_lossy(&output)
+        .lines()
+        .filter_map(|line| line.split(' ').next()?.parse::<u64>().ok())
+        .sum();
+    Ok(sizes)
+}
@@ -0,0 +1,166 @@
+// `syd drift`: files whose last backup from one machine differs from the last
+// backup of another, across every branch of the remote. A machine's version
+// no longer counts once another machine committed the file on top of it: that
+// is an update the first machine has yet to restore, not drift. What is left
+// are versions on histories that forked, such as per-machine branches.
+use crate::config::{Config, Entry};
+use crate::exec;
+use crate::files;
+use crate::git;
+use crate::manifest::Manifest;
+use crate::metadata;
+use crate::storage::{self, Storage};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct Version {
+    pub host: String,
+    pub date: String,
+    // Blob id of the file, None when that machine's last change deleted it.
+    pub blob: Option<String>,
+}
+
+pub struct Drift {
+    pub path: PathBuf,
+    pub versions: Vec<Version>,
+}
+
+pub fn drift(config: &Config) -> io::Result<Vec<Drift>> {
+    let mut drifts = Vec::new();
+    for repo in config.repo_configs()? {
+        let folder = repo.backup_folder();
+        if !folder.join(".git").exists() {
+            continue;
+        }
+        if repo.remote().is_some() {
+            git::fetch(&folder)?;
+        }
+        drifts.extend(repo_drift(&repo, &folder)?);
+    }
+    Ok(drifts)
+}
+
+fn repo_drift(config: &Config, folder: &Path) -> io::Result<Vec<Drift>> {
+    if !git::succeeds(folder, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+        return Ok(Vec::new());
+    }
+    let tracked = tracked_plain_files(config, folder)?;
+
+    // The newest commit per file and host, across all remote branches.
+    let mut latest: BTreeMap<(String, String), (String, String)> = BTreeMap::new();
+    for commit in metadata::history(folder, &["HEAD", "--remotes=origin"])? {
+        for file in commit.files.iter().filter(|file| tracked.contains_key(*file)) {
+            latest
+                .entry((file.clone(), commit.host.clone()))
+                .or_insert_with(|| (commit.hash.clone(), commit.date.clone()));
+        }
+    }
+    let objects: Vec<String> = latest.iter().map(|((file, _), (hash, _))| format!("{}:{}", hash, file)).collect();
+    let blobs = blob_ids(folder, &objects)?;
+
+    let mut by_file: BTreeMap<&String, Vec<(&String, Version)>> = BTreeMap::new();
+    for (((file, host), (hash, date)), blob) in latest.iter().zip(blobs) {
+        let version = Version {
+            host: host.clone(),
+            date: date.clone(),
+            blob,
+        };
+        by_file.entry(file).or_default().push((hash, version));
+    }
+    let mut ancestors = BTreeMap::new();
+    let mut drifts = Vec::new();
+    for (file, versions) in by_file {
+        let mut current = Vec::new();
+        for (hash, _) in &versions {
+            let mut superseded = false;
+            for (other, _) in &versions {
+                if other != hash && is_ancestor(folder, &mut ancestors, hash, other)? {
+                    superseded = true;
+                    break;
+                }
+            }
+            current.push(!superseded);
+        }
+        let versions: Vec<Version> = versions
+            .into_iter()
+            .zip(current)
+            .filter(|(_, current)| *current)
+            .map(|((_, version), _)| version)
+            .collect();
+        let distinct: BTreeSet<_> = versions.iter().filter_map(|version| version.blob.as_ref()).collect();
+        if distinct.len() > 1 {
+            drifts.push(Drift {
+                path: tracked[file].clone(),
+                versions,
+            });
+        }
+    }
+    Ok(drifts)
+}
+
+// Repo path to home path of the plainly stored files of the configured
+// entries and of the entries other machines recorded in the manifest.
+// Encrypted copies differ on every backup, so they cannot be compared.
+fn tracked_plain_files(config: &Config, folder: &Path) -> io::Result<BTreeMap<String, PathBuf>> {
+    let mut entries: Vec<Entry> = config.entries.clone();
+    if let Some(manifest) = Manifest::load(folder)? {
+        for file in &manifest.files {
+            if !entries.iter().any(|entry| entry.path == file.path) {
+                entries.push(manifest.resolve(file));
+            }
+        }
+    }
+    let mut tracked = BTreeMap::new();
+    for entry in &entries {
+        if storage::storage(entry) != Storage::Plain || entry.options.str("submodule").is_some() {
+            continue;
+        }
+        let destination = folder.join(entry.repo_path());
+        for relative in files::walk(&destination).unwrap_or_default() {
+            let stored = files::join(&destination, &relative);
+            if let Ok(name) = stored.strip_prefix(folder) {
+                tracked.insert(name.to_string_lossy().into_owned(), files::join(&entry.path, &relative));
+            }
+        }
+    }
+    Ok(tracked)
+}
+
+// Whether `old` is in the history of `new`, remembered across files.
+fn is_ancestor(
+    folder: &Path,
+    known: &mut BTreeMap<(String, String), bool>,
+    old: &str,
+    new: &str,
+) -> io::Result<bool> {
+    let key = (old.to_string(), new.to_string());
+    if let Some(&answer) = known.get(&key) {
+        return Ok(answer);
+    }
+    let answer = git::succeeds(folder, &["merge-base", "--is-ancestor", old, new])?;
+    known.insert(key, answer);
+    Ok(answer)
+}
+
+// Looks up `revision:path` objects in one `git cat-file` run.
+fn blob_ids(folder: &Path, objects: &[String]) -> io::Result<Vec<Option<String>>> {
+    let mut command = git::command(folder);
+    command.args(["cat-file", "--batch-check=%(objectname)"]);
+    let input: String = objects.iter().map(|object| format!("{}\n", object)).collect();
+    let output = exec::run(command, input.as_bytes())?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git cat-file failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let blobs: Vec<Option<String>> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| (!line.ends_with(" missing")).then(|| line.to_string()))
+        .collect();
+    if blobs.len() != objects.len() {
+        return Err(io::Error::other("git cat-file returned an unexpected number of objects"));
+    }
+    Ok(blobs)
+}
@@ -0,0 +1,59 @@
+// Files left next to their originals by editors: vim's `.name.swp` swap
+// files, emacs' `.#name` locks and `#name#` autosaves, and `name~` backups.
+// Directory walks over $HOME leave them out, so they are never committed. A
+// swap file or lock next to a tracked file means it is open and may be half
+// written, so backups skip it with a warning until the editor is done, unless
+// `files.open_in_editor` (or the entry's `open_in_editor`) is "backup".
+use crate::config::{Config, Entry};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub fn is_artifact(path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+    name.ends_with('~')
+        || name.starts_with(".#")
+        || (name.len() > 2 && name.starts_with('#') && name.ends_with('#'))
+        || (name.starts_with('.') && is_swap_extension(&name))
+}
+
+// .swp, then .swo, .swn and on down when vim finds the earlier ones taken.
+fn is_swap_extension(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.len() == 3 => {
+            extension.starts_with("sw") && (b'a'..=b'p').contains(&extension.as_bytes()[2])
+        }
+        _ => false,
+    }
+}
+
+pub fn skip_open(config: &Config, entry: &Entry) -> io::Result<bool> {
+    match entry
+        .options
+        .str("open_in_editor")
+        .or_else(|| config.settings.str("files.open_in_editor"))
+        .unwrap_or("skip")
+    {
+        "skip" => Ok(true),
+        "backup" => Ok(false),
+        other => Err(io::Error::other(format!(
+            "unknown open_in_editor {:?}, expected \"skip\" or \"backup\"",
+            other
+        ))),
+    }
+}
+
+// The swap file or lock showing that an editor has `path` open.
+pub fn open_marker(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    let dir = path.parent()?;
+    // Vim does not add a second dot to hidden files.
+    let swap = if name.starts_with('.') { name.to_string() } else { format!(".{}", name) };
+    let candidates = [format!("{}.swp", swap), format!("{}.swo", swap), format!(".#{}", name)];
+    candidates
+        .iter()
+        .map(|candidate| dir.join(candidate))
+        // Emacs locks are dangling symlinks naming the user, host and pid.
+        .find(|marker| marker.symlink_metadata().is_ok())
+}
@@ -0,0 +1,184 @@
+// JSON lines for external automation (notifications, dashboards) as things
+// happen: `backed_up` and `restored` per file, `conflict` when local backups
+// cannot be replayed on the remote, `push_failed`. They go to the descriptor
+// given with `--events-fd N`, or to `events.path`, which may be a FIFO, a
+// listening unix stream socket or a plain file to append to. Nothing ever
+// waits for a reader: with no reader on the FIFO, no listener on the socket or
+// a full pipe, events are dropped. `--non-interactive` runs also get them on
+// stderr, through `output::event`.
+use crate::config;
+use crate::log;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const SOCKET_TIMEOUT: Duration = Duration::from_millis(100);
+
+enum Target {
+    Fd(u32),
+    Path(PathBuf),
+}
+
+enum Sink {
+    File(File),
+    Fifo(File),
+    Socket(UnixStream),
+}
+
+struct Events {
+    target: Option<Target>,
+    // Opened on the first event and again after a failed write, so a reader
+    // that comes and goes keeps getting events.
+    sink: Option<Sink>,
+}
+
+static EVENTS: Mutex<Events> = Mutex::new(Events {
+    target: None,
+    sink: None,
+});
+
+fn events() -> std::sync::MutexGuard<'static, Events> {
+    EVENTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// `--events-fd`, which wins over the config.
+pub fn init_fd(fd: u32) {
+    let mut events = events();
+    events.target = Some(Target::Fd(fd));
+    events.sink = None;
+}
+
+// Called with `events.path` whenever the config is loaded.
+pub fn configure(path: Option<&str>) {
+    let mut events = events();
+    if let Some(Target::Fd(_)) = events.target {
+        return;
+    }
+    let path = path.map(config::expand_path);
+    let unchanged = match (&events.target, &path) {
+        (Some(Target::Path(old)), Some(new)) => old == new,
+        (None, None) => true,
+        _ => false,
+    };
+    if !unchanged {
+        events.target = path.map(Target::Path);
+        events.sink = None;
+    }
+}
+
+// Best effort, like the log.
+pub fn send(line: &str) {
+    let mut events = events();
+    if events.sink.is_none() {
+        events.sink = events.target.as_ref().and_then(|target| open(target).ok());
+    }
+    let Some(sink) = events.sink.as_mut() else {
+        return;
+    };
+    let line = format!("{}\n", line);
+    let written = match sink {
+        // One write of at most PIPE_BUF bytes reaches a pipe whole or not at
+        // all, so a full FIFO never ends up with half a line.
+        Sink::Fifo(_) if line.len() > libc::PIPE_BUF => {
+            drop(events);
+            log::warn(
+                &format!("dropped an event of {} bytes, more than a FIFO takes at once", line.len()),
+                &[],
+            );
+            return;
+        }
+        Sink::Fifo(fifo) => fifo.write(line.as_bytes()).is_ok_and(|written| written == line.len()),
+        Sink::File(file) => file.write_all(line.as_bytes()).is_ok(),
+        // A socket that timed out partway is closed, so the listener sees
+        // the connection end instead of the rest of the line in front of the
+        // next event.
+        Sink::Socket(socket) => socket.write_all(line.as_bytes()).is_ok(),
+    };
+    if !written {
+        events.sink = None;
+    }
+}
+
+fn open(target: &Target) -> io::Result<Sink> {
+    let path = match target {
+        Target::Fd(fd) => return inherited(*fd as i32),
+        Target::Path(path) => path,
+    };
+    if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        let socket = UnixStream::connect(path)?;
+        socket.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+        return Ok(Sink::Socket(socket));
+    }
+    // Opening a FIFO without a reader fails right away instead of waiting.
+    let file = OpenOptions::new().append(true).create(true).custom_flags(libc::O_NONBLOCK).open(path)?;
+    match file.metadata()?.file_type().is_fifo() {
+        true => Ok(Sink::Fifo(file)),
+        false => Ok(Sink::File(file)),
+    }
+}
+
+// The inherited descriptor is used as it is, since sockets cannot be reopened
+// through /dev/fd. Each sink gets a duplicate, so the descriptor survives a
+// failed write.
+fn inherited(fd: i32) -> io::Result<Sink> {
+    // A bad descriptor makes fcntl fail with EBADF.
+    let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if copy < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // `copy` is a fresh descriptor nothing else owns.
+    let file = File::from(unsafe { OwnedFd::from_raw_fd(copy) });
+    let file_type = file.metadata()?.file_type();
+    if file_type.is_socket() {
+        let socket = UnixStream::from(OwnedFd::from(file));
+        socket.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+        return Ok(Sink::Socket(socket));
+    }
+    if file_type.is_fifo() {
+        let flags = unsafe { libc::fcntl(copy, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(copy, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(Sink::Fifo(file));
+    }
+    Ok(Sink::File(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn only_fifos_drop_lines_longer_than_pipe_buf() {
+        let dir = std::env::temp_dir().join(format!("syd-events-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let long = format!("{{\"event\":\"test\",\"padding\":\"{}\"}}", "x".repeat(libc::PIPE_BUF));
+
+        let fifo = dir.join("fifo");
+        let name = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+        let mut reader = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&fifo).unwrap();
+        configure(Some(&fifo.to_string_lossy()));
+        send(&long);
+        send("{\"event\":\"short\"}");
+        let mut received = String::new();
+        let _ = reader.read_to_string(&mut received);
+        assert_eq!(received, "{\"event\":\"short\"}\n");
+
+        let file = dir.join("events.jsonl");
+        configure(Some(&file.to_string_lossy()));
+        send(&long);
+        assert_eq!(fs::read_to_string(&file).unwrap(), format!("{}\n", long));
+        configure(None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
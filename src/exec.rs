@@ -0,0 +1,39 @@
+use std::io::{self, Write};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+// Runs `command` with `input` on stdin and returns its stdout. Input is fed
+// from a separate thread so large files cannot deadlock on full pipes.
+pub fn pipe(command: Command, input: &[u8]) -> io::Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = run(command, input)?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+// Like `pipe`, but leaves interpreting the exit status to the caller.
+pub fn run(mut command: Command, input: &[u8]) -> io::Result<Output> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run {}: {}", program, e)))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let written = writer.join().unwrap_or_else(|_| Err(io::Error::other("stdin writer panicked")));
+    // A filter may legitimately exit without reading all of its input.
+    match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe && output.status.success() => Err(e),
+        _ => Ok(output),
+    }
+}
@@ -0,0 +1,152 @@
+// `syd export --format home-manager`: the tracked files as a home-manager
+// module, each linked from its copy in the backup folder, so a home can move
+// to Nix one file at a time or come back later. Files syd stores encrypted or
+// as deltas, or rewrites on restore, have no usable copy and are listed as
+// comments instead. home-manager only manages the home directory.
+use crate::config::{home_dir, Config, DEFAULT_REPO};
+use crate::git;
+use crate::manifest::Manifest;
+use crate::storage::{self, Storage};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+pub fn home_manager(config: &Config) -> io::Result<String> {
+    let home = home_dir();
+    let mut bindings = String::new();
+    let mut files = String::new();
+    // `repo_configs` keeps the [[repos]] tables in order after the default
+    // repo, which is left out when it has no entries. Repo names need not be
+    // Nix identifiers, so the bindings are numbered and the name is a comment.
+    let repos = config.repo_configs()?;
+    let first_named = repos.len() - config.repos.len();
+    for (index, repo) in repos.iter().enumerate() {
+        let repo_name = match index.checked_sub(first_named) {
+            Some(named) => config.repos[named].str("name").unwrap_or_default(),
+            None => DEFAULT_REPO,
+        };
+        let folder = repo.backup_folder();
+        let name = format!("repo_{}", index);
+        bindings.push_str(&format!("  {} = {}; # {}\n", name, nix_path(&folder), nix_string(repo_name)));
+        let manifest = Manifest::load(&folder)?;
+        for entry in &repo.entries {
+            let resolved = manifest.as_ref().map(|manifest| manifest.resolve(entry));
+            let entry = resolved.as_ref().unwrap_or(entry);
+            let rewritten = !entry.options.list("restore_filter").is_empty();
+            let skipped = match storage::storage(entry) {
+                Storage::Encrypted => Some("encrypted in the backup"),
+                Storage::Delta => Some("stored as a delta"),
+                Storage::Plain if rewritten => Some("rewritten by restore_filter"),
+                Storage::Plain => None,
+            };
+            for (target, source) in storage::stored_files(repo, entry, &folder)? {
+                let Ok(relative) = target.strip_prefix(&home) else {
+                    let target = nix_string(&target.to_string_lossy());
+                    files.push_str(&format!("    # {}: outside the home directory\n", target));
+                    continue;
+                };
+                let attribute = nix_string(&relative.to_string_lossy());
+                if let Some(reason) = skipped {
+                    files.push_str(&format!("    # {}: {}, not exported\n", attribute, reason));
+                    continue;
+                }
+                let stored = source.strip_prefix(&folder).unwrap_or(&source);
+                let source = format!("{} + {}", name, nix_string(&format!("/{}", stored.to_string_lossy())));
+                if fs::metadata(folder.join(stored))?.permissions().mode() & 0o111 != 0 {
+                    files.push_str(&format!("    {} = {{ source = {}; executable = true; }};\n", attribute, source));
+                } else {
+                    files.push_str(&format!("    {}.source = {};\n", attribute, source));
+                }
+            }
+        }
+    }
+    Ok(format!(
+        "# Generated by `syd export --format home-manager` on {}.\n\
+         # Import it from home.nix. The files are linked from the backup folders,\n\
+         # so `syd restore` or a pull there updates them; stop tracking a file in\n\
+         # syd once home-manager manages it.\n\
+         {{ ... }}:\n\n\
+         let\n{}in\n{{\n  home.file = {{\n{}  }};\n}}\n",
+        git::hostname(),
+        bindings,
+        files
+    ))
+}
+
+// A path literal when Nix can parse it as one.
+fn nix_path(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let plain = text.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+".contains(c));
+    match plain && text.len() > 1 {
+        true => text.trim_end_matches('/').to_string(),
+        false => format!("/. + {}", nix_string(&text)),
+    }
+}
+
+// Line breaks are escaped too, so the string is also safe in a `#` comment.
+fn nix_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use std::path::PathBuf;
+
+    #[test]
+    fn strings_are_quoted_for_attributes_and_comments() {
+        assert_eq!(nix_string("plain"), "\"plain\"");
+        assert_eq!(nix_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(nix_string("${HOME}"), "\"\\${HOME}\"");
+        assert_eq!(nix_string("new\nline\r\tend"), "\"new\\nline\\r\\tend\"");
+        assert_eq!(nix_path(Path::new("/home/me/.dotfiles/")), "/home/me/.dotfiles");
+        assert_eq!(nix_path(Path::new("/home/me/my files")), "/. + \"/home/me/my files\"");
+        assert_eq!(nix_path(Path::new("/")), "/. + \"/\"");
+    }
+
+    #[test]
+    fn repos_get_unique_names_and_unusable_copies_are_comments() {
+        let dir = std::env::temp_dir().join(format!("syd-export-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let text = format!(
+            "[backup]\nfolder = \"{dir}/default\"\n\n\
+             [[repos]]\nname = \"work repo\"\nfolder = \"{dir}/a\"\n\n\
+             [[repos]]\nname = \"work_repo\\n}}\"\nfolder = \"{dir}/b\"\n\n\
+             [[files]]\npath = \"~/plain\"\nrepo = \"work repo\"\n\n\
+             [[files]]\npath = \"~/secret\"\nencrypt = true\nrepo = \"work_repo\\n}}\"\n\n\
+             [[files]]\npath = \"~/big\"\ndelta = true\nrepo = \"work_repo\\n}}\"\n\n\
+             [[files]]\npath = \"~/filtered\"\nrestore_filter = [\"cat\"]\nrepo = \"work_repo\\n}}\"\n",
+            dir = dir.display()
+        );
+        let (settings, entries, repos) = config::parse(&text).unwrap();
+        let config = Config {
+            path: PathBuf::from("syd.toml"),
+            settings,
+            entries,
+            repos,
+        };
+        for (folder, file) in [("a", "plain"), ("b", "secret.gpg"), ("b", "big.syd-base"), ("b", "filtered")] {
+            fs::create_dir_all(dir.join(folder)).unwrap();
+            fs::write(dir.join(folder).join(file), "x").unwrap();
+        }
+        let module = home_manager(&config).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(module.contains(&format!("  repo_0 = {}/a; # \"work repo\"\n", dir.display())), "{}", module);
+        assert!(module.contains(&format!("  repo_1 = {}/b; # \"work_repo\\n}}\"\n", dir.display())), "{}", module);
+        assert!(module.contains("    \"plain\".source = repo_0 + \"/plain\";\n"), "{}", module);
+        assert!(module.contains("    # \"secret\": encrypted in the backup, not exported\n"), "{}", module);
+        assert!(module.contains("    # \"big\": stored as a delta, not exported\n"), "{}", module);
+        assert!(module.contains("    # \"filtered\": rewritten by restore_filter, not exported\n"), "{}", module);
+        assert!(!module.contains("repo_1 +"), "{}", module);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config::Config;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::{self, fs::MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+// `files.durability = "fsync"` syncs every copy written into the backup folder
+// and the directories holding it before moving on, and has git sync its
+// objects, so a removable drive pulled right after a backup keeps the latest
+// copies.
+static FSYNC: AtomicBool = AtomicBool::new(false);
+
+pub fn init(config: &Config) -> io::Result<()> {
+    let fsync = match config.settings.str("files.durability").unwrap_or("fast") {
+        "fast" => false,
+        "fsync" => true,
+        other => return Err(io::Error::other(format!("unknown files.durability {:?}", other))),
+    };
+    FSYNC.store(fsync, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn fsync() -> bool {
+    FSYNC.load(Ordering::Relaxed)
+}
+
+// Lists the files below `root` relative to it. A plain file yields a single
+// empty path, so `join(root, relative)` addresses the file itself.
+pub fn walk(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    walk_into(root, Path::new(""), &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn walk_into(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let path = join(root, relative);
+    if !path.is_dir() {
+        files.push(relative.to_path_buf());
+        return Ok(());
+    }
+    for child in fs::read_dir(&path)? {
+        let child = child?;
+        if child.file_name() == ".git" {
+            continue;
+        }
+        walk_into(root, &relative.join(child.file_name()), files)?;
+    }
+    Ok(())
+}
+
+pub fn join(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+// Writes `content` to `destination`, taking the permissions from `like`.
+pub fn write_file(destination: &Path, content: &[u8], like: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        create_dirs(parent)?;
+    }
+    write(destination, content)?;
+    fs::set_permissions(destination, fs::metadata(like)?.permissions())
+}
+
+pub fn copy_file(source: &Path, destination: &Path) -> io::Result<u64> {
+    if let Some(parent) = destination.parent() {
+        create_dirs(parent)?;
+    }
+    let size = fs::copy(source, destination)?;
+    if fsync() {
+        fs::File::open(destination)?.sync_all()?;
+        sync_parent(destination)?;
+    }
+    Ok(size)
+}
+
+// `fs::write`, honouring `files.durability`.
+pub fn write(path: &Path, content: &[u8]) -> io::Result<()> {
+    if !fsync() {
+        return fs::write(path, content);
+    }
+    let mut file = fs::File::create(path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    sync_parent(path)
+}
+
+// Writes into the backup folder go to a temporary file that is renamed into
+// place, so commands reading it meanwhile see the old or the new copy, never
+// half of one.
+pub fn replace(path: &Path, content: &[u8]) -> io::Result<()> {
+    replace_with(path, content, None)
+}
+
+// `replace`, creating parent directories and taking the permissions from
+// `like`.
+pub fn replace_file(destination: &Path, content: &[u8], like: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        create_dirs(parent)?;
+    }
+    replace_with(destination, content, Some(like))
+}
+
+fn replace_with(path: &Path, content: &[u8], like: Option<&Path>) -> io::Result<()> {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".syd-tmp");
+    let tmp = path.with_file_name(name);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(content)?;
+    if fsync() {
+        file.sync_all()?;
+    }
+    if let Some(like) = like {
+        fs::set_permissions(&tmp, fs::metadata(like)?.permissions())?;
+    }
+    fs::rename(&tmp, path)?;
+    match fsync() {
+        true => sync_parent(path),
+        false => Ok(()),
+    }
+}
+
+// `fs::create_dir_all`; with fsync the new directory entries are synced too.
+pub fn create_dirs(dir: &Path) -> io::Result<()> {
+    let missing: Vec<&Path> = dir.ancestors().take_while(|ancestor| !ancestor.exists()).collect();
+    fs::create_dir_all(dir)?;
+    if fsync() {
+        for created in missing.iter().rev() {
+            sync_parent(created)?;
+        }
+    }
+    Ok(())
+}
+
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => fs::File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+pub fn set_mtime(path: &Path, mtime: SystemTime) -> io::Result<()> {
+    fs::File::options().write(true).open(path)?.set_modified(mtime)
+}
+
+// Sizes for people: `512 B`, `1.5 KiB`, `20.0 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// Gives `path` and the directories created for it below `root` the owner of
+// `root`, for restoring into another user's home as root.
+pub fn adopt_owner(root: &Path, path: &Path) -> io::Result<()> {
+    let owner = fs::metadata(root)?;
+    let mut current = Some(path);
+    while let Some(path) = current.filter(|path| *path != root && path.starts_with(root)) {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.uid() != owner.uid() || metadata.gid() != owner.gid() {
+            unix::fs::lchown(path, Some(owner.uid()), Some(owner.gid()))?;
+        }
+        current = path.parent();
+    }
+    Ok(())
+}
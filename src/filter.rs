@@ -0,0 +1,360 @@
+// Per-entry content filters. On backup `filter_lines` (extended regular
+// expressions, matched by grep) drops volatile lines and `filter` runs the
+// file through a chain of steps, e.g. to normalize it so edits that change
+// nothing meaningful do not make commits. On restore `restore_filter` is the
+// inverse (nothing by default), `keep_local_lines` puts the filtered lines of
+// the existing file back, and `validate_cmd` has to accept the result before
+// it is written. Regions between `syd:local-begin` and `syd:local-end` lines
+// are machine-specific in every file: backups store only the markers,
+// restores keep the local lines.
+use crate::config::{self, Entry};
+use crate::exec;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use std::process::{self, Command};
+
+const LOCAL_BEGIN: &[u8] = b"syd:local-begin";
+const LOCAL_END: &[u8] = b"syd:local-end";
+
+pub fn clean(entry: &Entry, content: Vec<u8>) -> io::Result<Vec<u8>> {
+    let patterns = entry.options.list("filter_lines");
+    let mut content = strip_local(&content);
+    if !patterns.is_empty() {
+        let matching = matching_lines(&patterns, &content)?;
+        if !matching.is_empty() {
+            content = lines(&content)
+                .enumerate()
+                .filter(|(number, _)| !matching.contains_key(number))
+                .flat_map(|(_, line)| line.iter().copied())
+                .collect();
+        }
+    }
+    pipeline(&entry.options.list("filter"), content, None)
+}
+
+// `target` is the file about to be replaced; it is passed to the restore
+// filter as $SYD_TARGET and is where local lines are taken from.
+pub fn smudge(entry: &Entry, content: Vec<u8>, target: &Path) -> io::Result<Vec<u8>> {
+    let mut content = pipeline(&entry.options.list("restore_filter"), content, Some(target))?;
+    if let Ok(local) = fs::read(target) {
+        content = fill_local(&content, &local);
+    }
+    let patterns = entry.options.list("filter_lines");
+    if patterns.is_empty() || !entry.options.bool("keep_local_lines").unwrap_or(false) {
+        return Ok(content);
+    }
+    let Ok(local) = fs::read(target) else {
+        return Ok(content);
+    };
+    // Local lines go back to the line numbers they had in the local file.
+    let keep = matching_lines(&patterns, &local)?;
+    if keep.is_empty() {
+        return Ok(content);
+    }
+    let mut merged: Vec<Vec<u8>> = lines(&content).map(<[u8]>::to_vec).collect();
+    if merged.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        merged.last_mut().expect("last line").push(b'\n');
+    }
+    for (number, line) in keep {
+        let mut line = line;
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        merged.insert(number.min(merged.len()), line);
+    }
+    Ok(merged.concat())
+}
+
+// Runs `validate_cmd` (e.g. `zsh -n`, `tmux -f {} -C exit`) on `content`
+// written to a temporary file with the name of `target`. The file's path
+// replaces `{}`, or is appended when there is none, and the command has to
+// exit 0 for the content to be deployed.
+pub fn validate(entry: &Entry, content: &[u8], target: &Path) -> io::Result<()> {
+    let Some(script) = entry.options.str("validate_cmd") else {
+        return Ok(());
+    };
+    let dir = env::temp_dir().join(format!("syd-validate-{}", process::id()));
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    let file = dir.join(target.file_name().unwrap_or("file".as_ref()));
+    let output = (|| {
+        OpenOptions::new().create(true).truncate(true).write(true).mode(0o600).open(&file)?.write_all(content)?;
+        // The path is passed as $1 so it needs no quoting.
+        let line = match script.contains("{}") {
+            true => script.replace("{}", "\"$1\""),
+            false => format!("{} \"$1\"", script),
+        };
+        let mut command = shell(&line);
+        command.arg("sh").arg(&file).env("SYD_TARGET", target).current_dir(&dir);
+        exec::run(command, b"")
+    })();
+    fs::remove_dir_all(&dir)?;
+    let output = output?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if message.is_empty() {
+        message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    }
+    Err(io::Error::other(format!(
+        "not restoring {}: `{}` rejected it{}{}",
+        config::display_path(target),
+        script,
+        if message.is_empty() { "" } else { ": " },
+        message
+    )))
+}
+
+// Whether `a` and `b` are the same apart from lines matching `patterns`.
+pub fn same_ignoring(patterns: &[String], a: &[u8], b: &[u8]) -> io::Result<bool> {
+    let kept = |content: &[u8]| -> io::Result<Vec<Vec<u8>>> {
+        let matching = matching_lines(patterns, content)?;
+        Ok(lines(content)
+            .enumerate()
+            .filter(|(number, _)| !matching.contains_key(number))
+            .map(|(_, line)| line.to_vec())
+            .collect())
+    };
+    Ok(kept(a)? == kept(b)?)
+}
+
+// Runs the steps of a `filter` or `restore_filter` chain in order: the
+// built-in ones by name, anything else as a shell command from stdin to
+// stdout, with the file being replaced on restore in $SYD_TARGET.
+fn pipeline(steps: &[String], content: Vec<u8>, target: Option<&Path>) -> io::Result<Vec<u8>> {
+    let mut content = content;
+    for step in steps {
+        content = match step.as_str() {
+            "strip-trailing-whitespace" => lines(&content)
+                .flat_map(|line| {
+                    let body = line.strip_suffix(b"\n").unwrap_or(line);
+                    let (body, cr) = match body.strip_suffix(b"\r") {
+                        Some(body) => (body, &b"\r"[..]),
+                        None => (body, &b""[..]),
+                    };
+                    let end = body.iter().rposition(|&b| b != b' ' && b != b'\t').map_or(0, |last| last + 1);
+                    let newline = if line.ends_with(b"\n") { &b"\n"[..] } else { &b""[..] };
+                    [&body[..end], cr, newline].concat()
+                })
+                .collect(),
+            "sort-lines" => {
+                let mut sorted: Vec<Vec<u8>> = lines(&content).map(<[u8]>::to_vec).collect();
+                if let Some(last) = sorted.last_mut().filter(|last| !last.ends_with(b"\n")) {
+                    last.push(b'\n');
+                }
+                sorted.sort();
+                sorted.concat()
+            }
+            "final-newline" => {
+                let end = content.iter().rposition(|&b| b != b'\n').map_or(0, |last| last + 1);
+                content.truncate(end);
+                if !content.is_empty() {
+                    content.push(b'\n');
+                }
+                content
+            }
+            script => {
+                let mut command = shell(script);
+                if let Some(target) = target {
+                    command.env("SYD_TARGET", target);
+                }
+                exec::pipe(command, &content).map_err(|e| io::Error::other(format!("filter `{}`: {}", script, e)))?
+            }
+        };
+    }
+    Ok(content)
+}
+
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+// The lines strictly between each pair of local markers; a begin marker
+// without an end is left alone.
+fn local_regions(content: &[u8]) -> Vec<(usize, usize)> {
+    let marks = |line: &[u8], marker: &[u8]| line.windows(marker.len()).any(|window| window == marker);
+    let mut regions = Vec::new();
+    let mut begin = None;
+    for (number, line) in lines(content).enumerate() {
+        if marks(line, LOCAL_BEGIN) {
+            begin = Some(number);
+        } else if let (true, Some(start)) = (marks(line, LOCAL_END), begin) {
+            regions.push((start + 1, number));
+            begin = None;
+        }
+    }
+    regions
+}
+
+fn strip_local(content: &[u8]) -> Vec<u8> {
+    let regions = local_regions(content);
+    if regions.is_empty() {
+        return content.to_vec();
+    }
+    lines(content)
+        .enumerate()
+        .filter(|(number, _)| !regions.iter().any(|(start, end)| (*start..*end).contains(number)))
+        .flat_map(|(_, line)| line.iter().copied())
+        .collect()
+}
+
+// Puts the lines of the local file's regions into the stored content's
+// regions, the first into the first and so on.
+fn fill_local(content: &[u8], local: &[u8]) -> Vec<u8> {
+    let stored = local_regions(content);
+    let kept = local_regions(local);
+    if stored.is_empty() || kept.is_empty() {
+        return content.to_vec();
+    }
+    // Regions without a local counterpart keep the stored lines.
+    let replaced = &stored[..stored.len().min(kept.len())];
+    let local_lines: Vec<&[u8]> = lines(local).collect();
+    let mut merged = Vec::with_capacity(content.len());
+    for (number, line) in lines(content).enumerate() {
+        if let Some(index) = replaced.iter().position(|(_, end)| *end == number) {
+            let (start, end) = kept[index];
+            local_lines[start..end].iter().for_each(|kept_line| merged.extend_from_slice(kept_line));
+        }
+        if !replaced.iter().any(|(start, end)| (*start..*end).contains(&number)) {
+            merged.extend_from_slice(line);
+        }
+    }
+    merged
+}
+
+// Lines including their trailing newline.
+fn lines(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+    content.split_inclusive(|&b| b == b'\n')
+}
+
+// The zero-based numbers and contents of the lines matching any pattern.
+fn matching_lines(patterns: &[String], content: &[u8]) -> io::Result<BTreeMap<usize, Vec<u8>>> {
+    let mut command = Command::new("grep");
+    command.args(["--text", "--line-number", "--extended-regexp"]);
+    for pattern in patterns {
+        command.arg("--regexp").arg(pattern);
+    }
+    let output = exec::run(command, content)?;
+    // grep exits with 1 when nothing matched.
+    if output.status.code() != Some(0) && output.status.code() != Some(1) {
+        return Err(io::Error::other(format!(
+            "filter_lines: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let all: Vec<&[u8]> = lines(content).collect();
+    let mut matching = BTreeMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some(number) = line.split(':').next().and_then(|n| n.parse::<usize>().ok()) else {
+            continue;
+        };
+        if let Some(matched) = number.checked_sub(1).and_then(|index| all.get(index)) {
+            matching.insert(number - 1, matched.to_vec());
+        }
+    }
+    Ok(matching)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(options: &str) -> Entry {
+        let text = format!("[[files]]\npath = \"/tmp/syd-filter-test\"\n{}", options);
+        let (_, mut entries, _) = config::parse(&text).unwrap();
+        entries.remove(0)
+    }
+
+    fn steps(steps: &[&str]) -> Vec<String> {
+        steps.iter().map(|step| step.to_string()).collect()
+    }
+
+    #[test]
+    fn built_in_steps() {
+        let run = |step: &str, content: &[u8]| pipeline(&steps(&[step]), content.to_vec(), None).unwrap();
+        assert_eq!(run("strip-trailing-whitespace", b"a  \nb\t\r\n  \nc "), b"a\nb\r\n\nc");
+        assert_eq!(run("sort-lines", b"b\nc\na"), b"a\nb\nc\n");
+        assert_eq!(run("final-newline", b"text\n\n\n"), b"text\n");
+        assert_eq!(run("final-newline", b"text"), b"text\n");
+        assert_eq!(run("final-newline", b"\n\n"), b"");
+    }
+
+    #[test]
+    fn steps_run_in_order() {
+        let chain = steps(&["tr a-z A-Z", "sort-lines", "sed 1d"]);
+        assert_eq!(pipeline(&chain, b"b\nc\na\n".to_vec(), None).unwrap(), b"B\nC\n");
+        let reversed = steps(&["sed 1d", "tr a-z A-Z", "sort-lines"]);
+        assert_eq!(pipeline(&reversed, b"b\nc\na\n".to_vec(), None).unwrap(), b"A\nC\n");
+    }
+
+    #[test]
+    fn failing_steps_name_the_step() {
+        let error = pipeline(&steps(&["final-newline", "exit 3"]), b"x".to_vec(), None).unwrap_err();
+        assert!(error.to_string().starts_with("filter `exit 3`"), "{}", error);
+    }
+
+    #[test]
+    fn restore_steps_see_the_target() {
+        let target = Path::new("/tmp/syd-filter-target");
+        let output = pipeline(&steps(&["printf %s \"$SYD_TARGET\""]), Vec::new(), Some(target)).unwrap();
+        assert_eq!(output, b"/tmp/syd-filter-target");
+    }
+
+    #[test]
+    fn clean_drops_filtered_lines_before_the_chain() {
+        let entry = entry("filter_lines = [\"^token=\"]\nfilter = [\"sort-lines\"]\n");
+        let cleaned = clean(&entry, b"zoom=2\ntoken=secret\nalpha=1\n".to_vec()).unwrap();
+        assert_eq!(cleaned, b"alpha=1\nzoom=2\n");
+    }
+
+    const STORED: &[u8] = b"shared 1\n# syd:local-begin\n# syd:local-end\nshared 2\n";
+
+    #[test]
+    fn backups_keep_only_the_markers() {
+        let local = b"shared 1\n# syd:local-begin\nexport GPU=1\n# syd:local-end\nshared 2\n";
+        assert_eq!(strip_local(local), STORED);
+        assert_eq!(local_regions(local), [(2, 3)]);
+    }
+
+    #[test]
+    fn restores_splice_the_local_lines_in() {
+        let local = b"old\n# syd:local-begin\nexport GPU=1\nexport DPI=2\n# syd:local-end\n";
+        let merged = fill_local(STORED, local);
+        assert_eq!(merged, b"shared 1\n# syd:local-begin\nexport GPU=1\nexport DPI=2\n# syd:local-end\nshared 2\n");
+        // The stored side is the same whatever the local region held.
+        assert_eq!(strip_local(&merged), STORED);
+    }
+
+    #[test]
+    fn regions_pair_up_in_order() {
+        let stored = b"<\nsyd:local-begin\nstored a\nsyd:local-end\n-\nsyd:local-begin\nstored b\nsyd:local-end\n";
+        let local = b"syd:local-begin\nlocal a\nsyd:local-end\n";
+        // The second region has no local counterpart and keeps its lines.
+        let merged = fill_local(stored, local);
+        let expected = b"<\nsyd:local-begin\nlocal a\nsyd:local-end\n-\nsyd:local-begin\nstored b\nsyd:local-end\n";
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn unterminated_regions_are_left_alone() {
+        let content = b"a\nsyd:local-begin\nb\n";
+        assert!(local_regions(content).is_empty());
+        assert_eq!(strip_local(content), content);
+        assert_eq!(fill_local(STORED, content), STORED);
+    }
+
+    #[test]
+    fn smudge_takes_local_lines_from_the_target() {
+        let target = env::temp_dir().join(format!("syd-filter-smudge-{}", process::id()));
+        fs::write(&target, b"x\n# syd:local-begin\nmine\n# syd:local-end\n").unwrap();
+        let restored = smudge(&entry(""), STORED.to_vec(), &target);
+        fs::remove_file(&target).unwrap();
+        assert_eq!(restored.unwrap(), b"shared 1\n# syd:local-begin\nmine\n# syd:local-end\nshared 2\n");
+    }
+}
@@ -0,0 +1,234 @@
+// Pull requests for backups that could not go to a protected branch, opened
+// through the forge's REST API with curl. The forge is `push.forge` (github,
+// gitlab or gitea), guessed for github.com and gitlab.com; its API lives at
+// `push.forge_api`, by default derived from the remote's host. The token
+// comes from `push.forge_token` or $SYD_FORGE_TOKEN. The size of the remote
+// repo is read from there too, for the quota checks in `doctor`.
+use crate::config::Config;
+use crate::http;
+use crate::json;
+use crate::log;
+use std::env;
+use std::io;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Forge {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+// The project the remote points at and how to reach its API.
+struct Project {
+    forge: Forge,
+    host: String,
+    path: String,
+    api: String,
+    headers: Vec<String>,
+}
+
+fn project(config: &Config, needs_token: bool) -> io::Result<Project> {
+    let remote = config.remote().unwrap_or_default();
+    let (host, path) = parse_remote(remote)
+        .ok_or_else(|| io::Error::other(format!("cannot tell the forge project from the remote {:?}", remote)))?;
+    let forge = match config.settings.str("push.forge") {
+        Some("github") => Forge::GitHub,
+        Some("gitlab") => Forge::GitLab,
+        Some("gitea") => Forge::Gitea,
+        Some(other) => return Err(io::Error::other(format!("unknown push.forge {:?}", other))),
+        None if host == "github.com" => Forge::GitHub,
+        None if host == "gitlab.com" => Forge::GitLab,
+        None => return Err(io::Error::other(format!("set push.forge to the kind of forge {} runs", host))),
+    };
+    let api = match config.settings.str("push.forge_api") {
+        Some(api) => api.trim_end_matches('/').to_string(),
+        None => match forge {
+            Forge::GitHub if host == "github.com" => "https://api.github.com".to_string(),
+            Forge::GitHub => format!("https://{}/api/v3", host),
+            Forge::GitLab => format!("https://{}/api/v4", host),
+            Forge::Gitea => format!("https://{}/api/v1", host),
+        },
+    };
+    let mut headers = vec!["Accept: application/json".to_string()];
+    let token = match (config.settings.str("push.forge_token"), env::var("SYD_FORGE_TOKEN")) {
+        (Some(token), _) => Some(token.to_string()),
+        (None, Ok(token)) if !token.is_empty() => Some(token),
+        _ if needs_token => {
+            return Err(io::Error::other("push.pull_request needs push.forge_token or $SYD_FORGE_TOKEN"));
+        }
+        _ => None,
+    };
+    if let Some(token) = token {
+        headers.push(match forge {
+            Forge::GitHub => format!("Authorization: Bearer {}", token),
+            Forge::GitLab => format!("PRIVATE-TOKEN: {}", token),
+            Forge::Gitea => format!("Authorization: token {}", token),
+        });
+    }
+    Ok(Project {
+        forge,
+        host,
+        path,
+        api,
+        headers,
+    })
+}
+
+// Opens a pull request from `topic` into `base` unless one is open already.
+// Returns its URL when a new one was created.
+pub fn open_pull_request(
+    config: &Config,
+    topic: &str,
+    base: &str,
+    title: &str,
+    body: &str,
+) -> io::Result<Option<String>> {
+    let Project {
+        forge,
+        host,
+        path,
+        api,
+        mut headers,
+    } = project(config, true)?;
+    headers.push("Content-Type: application/json".to_string());
+    let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+
+    let (list, create, existing, payload) = match forge {
+        Forge::GitLab => {
+            let project = format!("{}/projects/{}/merge_requests", api, path.replace('/', "%2F"));
+            (
+                format!("{}?state=opened&source_branch={}", project, topic),
+                project,
+                format!("\"source_branch\":{}", json::string(topic)),
+                json::object(&[
+                    ("source_branch", json::string(topic)),
+                    ("target_branch", json::string(base)),
+                    ("title", json::string(title)),
+                    ("description", json::string(body)),
+                ]),
+            )
+        }
+        Forge::GitHub | Forge::Gitea => {
+            let owner = path.split('/').next().unwrap_or_default();
+            let pulls = format!("{}/repos/{}/pulls", api, path);
+            (
+                format!("{}?state=open&head={}:{}", pulls, owner, topic),
+                pulls,
+                format!("\"ref\":{}", json::string(topic)),
+                json::object(&[
+                    ("head", json::string(topic)),
+                    ("base", json::string(base)),
+                    ("title", json::string(title)),
+                    ("body", json::string(body)),
+                ]),
+            )
+        }
+    };
+    let open = http::request("GET", &list, &headers, None)?;
+    if open.replace(": ", ":").contains(&existing) {
+        return Ok(None);
+    }
+    let created = http::request("POST", &create, &headers, Some(&payload))?;
+    let (key, page) = match forge {
+        Forge::GitHub => ("number", "pull"),
+        Forge::GitLab => ("iid", "-/merge_requests"),
+        Forge::Gitea => ("number", "pulls"),
+    };
+    let number = field(&created, key).and_then(leading_number).unwrap_or_default();
+    let url = format!("https://{}/{}/{}/{}", host, path, page, number);
+    log::info("opened a pull request", &[("topic", json::string(topic)), ("url", json::string(&url))]);
+    Ok(Some(url))
+}
+
+// `host` and `owner/repo` of git@host:owner/repo.git, ssh://git@host/owner/repo
+// and https://host/owner/repo.git remotes.
+fn parse_remote(remote: &str) -> Option<(String, String)> {
+    let (host, path) = match remote.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?,
+        None => remote.split_once(':')?,
+    };
+    let host = host.rsplit('@').next()?.split(':').next()?;
+    let path = path.trim_matches('/').trim_end_matches(".git");
+    match host.is_empty() || !path.contains('/') {
+        true => None,
+        false => Some((host.to_string(), path.to_string())),
+    }
+}
+
+// The size of the remote repo as the forge reports it, and the limit it is
+// measured against: `push.quota` in bytes, or GitHub's recommended 1 GB and
+// GitLab's 10 GB on gitlab.com. Gitea has no limit of its own.
+pub struct RemoteSize {
+    pub bytes: u64,
+    pub limit: Option<u64>,
+}
+
+pub fn remote_size(config: &Config) -> io::Result<RemoteSize> {
+    let project = project(config, false)?;
+    let headers: Vec<&str> = project.headers.iter().map(String::as_str).collect();
+    let bytes = match project.forge {
+        // GitLab only shows statistics to project members.
+        Forge::GitLab => {
+            let url = format!("{}/projects/{}?statistics=true", project.api, project.path.replace('/', "%2F"));
+            let response = http::request("GET", &url, &headers, None)?;
+            field(&response, "statistics")
+                .and_then(|statistics| field(statistics, "repository_size"))
+                .and_then(leading_number)
+        }
+        // In KiB.
+        Forge::GitHub | Forge::Gitea => {
+            let url = format!("{}/repos/{}", project.api, project.path);
+            let response = http::request("GET", &url, &headers, None)?;
+            field(&response, "size").and_then(leading_number).map(|kib| kib * 1024)
+        }
+    };
+    let Some(bytes) = bytes else {
+        return Err(io::Error::other(format!("{} did not report the size of {}", project.api, project.path)));
+    };
+    let limit = match config.settings.int("push.quota") {
+        Some(quota) => u64::try_from(quota).ok().filter(|quota| *quota > 0),
+        None => match project.forge {
+            Forge::GitHub => Some(1 << 30),
+            Forge::GitLab if project.host == "gitlab.com" => Some(10 << 30),
+            Forge::GitLab | Forge::Gitea => None,
+        },
+    };
+    Ok(RemoteSize { bytes, limit })
+}
+
+// The value of `key` in the outermost object of a JSON response, as raw text
+// running to the end of the response. Nested objects and strings are skipped,
+// so keys of the same name deeper down do not match.
+fn field<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("\"{}\"", key);
+    let bytes = json.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let after = i + pattern.len();
+                if depth == 1 && json[i..].starts_with(&pattern) {
+                    if let Some(value) = json[after..].trim_start().strip_prefix(':') {
+                        return Some(value.trim_start());
+                    }
+                }
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth <= 1 => return None,
+            b'}' | b']' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn leading_number(text: &str) -> Option<u64> {
+    let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    text[..end].parse().ok()
+}
@@ -0,0 +1,193 @@
+use crate::files;
+use crate::output;
+use crate::time;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+#[derive(Debug)]
+pub struct Conflict {
+    pub files: Vec<String>,
+    // Where the steps to resolve it were written, see `conflicts`.
+    pub report: Option<PathBuf>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "conflicting remote changes in {}", self.files.join(", "))?;
+        match &self.report {
+            Some(report) => write!(f, "; see {} for how to resolve them", report.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+pub fn conflict(error: &io::Error) -> Option<&Conflict> {
+    error.get_ref().and_then(|e| e.downcast_ref::<Conflict>())
+}
+
+pub fn command(repo: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(repo);
+    if files::fsync() {
+        command.args(["-c", "core.fsync=all"]);
+    }
+    if let Some(now) = time::fixed_now() {
+        let date = format!("@{} +0000", now);
+        command.env("GIT_AUTHOR_DATE", &date).env("GIT_COMMITTER_DATE", &date);
+    }
+    // Credential prompts would hang provisioning runs.
+    if output::machine_readable() {
+        command.env("GIT_TERMINAL_PROMPT", "0");
+    }
+    command
+}
+
+pub fn run(repo: &Path, args: &[&str]) -> io::Result<Output> {
+    command(repo).args(args).output()
+}
+
+pub fn git(repo: &Path, args: &[&str]) -> io::Result<String> {
+    let output = run(repo, args)?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+pub fn succeeds(repo: &Path, args: &[&str]) -> io::Result<bool> {
+    Ok(run(repo, args)?.status.success())
+}
+
+pub fn create_local_repo(folder: &Path, branch: &str, remote: Option<&str>) -> io::Result<()> {
+    if !folder.join(".git").exists() {
+        git(folder, &["init", "--quiet"])?;
+        git(folder, &["symbolic-ref", "HEAD", &format!("refs/heads/{}", branch)])?;
+    }
+    if git(folder, &["config", "user.email"]).is_err() {
+        git(folder, &["config", "user.name", "syd"])?;
+        git(folder, &["config", "user.email", &format!("syd@{}", hostname())])?;
+    }
+    if let Some(remote) = remote {
+        match git(folder, &["remote", "get-url", "origin"]) {
+            Ok(url) if url == remote => {}
+            Ok(_) => {
+                git(folder, &["remote", "set-url", "origin", remote])?;
+            }
+            Err(_) => {
+                git(folder, &["remote", "add", "origin", remote])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Stages everything except submodules, whose checked out commit is managed
+// by `update_submodules` and never recorded by a backup.
+pub fn add_all(folder: &Path) -> io::Result<()> {
+    let excludes: Vec<String> = submodule_paths(folder)?
+        .iter()
+        .map(|path| format!(":(exclude){}", path))
+        .collect();
+    let mut args = vec!["add", "--all", "--", "."];
+    args.extend(excludes.iter().map(String::as_str));
+    git(folder, &args).map(|_| ())
+}
+
+pub fn submodule_paths(folder: &Path) -> io::Result<Vec<String>> {
+    if !folder.join(".gitmodules").exists() {
+        return Ok(Vec::new());
+    }
+    let output = run(folder, &["config", "--file", ".gitmodules", "--get-regexp", r"^submodule\..*\.path$"])?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' ').map(|(_, path)| path.to_string()))
+        .collect())
+}
+
+// Registers `url` as a submodule at `path` unless it already is one.
+pub fn add_submodule(folder: &Path, path: &str, url: &str) -> io::Result<()> {
+    if submodule_paths(folder)?.iter().any(|existing| existing == path) {
+        return Ok(());
+    }
+    git(folder, &["submodule", "add", "--quiet", "--", url, path]).map(|_| ())
+}
+
+pub fn update_submodules(folder: &Path) -> io::Result<()> {
+    if !folder.join(".gitmodules").exists() {
+        return Ok(());
+    }
+    git(folder, &["submodule", "update", "--init", "--recursive", "--quiet"]).map(|_| ())
+}
+
+pub fn has_staged_changes(folder: &Path) -> io::Result<bool> {
+    let has_head = succeeds(folder, &["rev-parse", "--verify", "--quiet", "HEAD"])?;
+    if !has_head {
+        return Ok(!git(folder, &["ls-files"])?.is_empty());
+    }
+    Ok(!succeeds(folder, &["diff", "--cached", "--quiet"])?)
+}
+
+pub fn commit(folder: &Path, message: &str) -> io::Result<()> {
+    git(folder, &["commit", "--quiet", "-m", message]).map(|_| ())
+}
+
+// Replays local backup commits on top of the fetched remote branch. A rebase
+// that stops on conflicts is aborted so the repo is left as it was.
+pub fn sync_with_remote(folder: &Path, branch: &str) -> io::Result<()> {
+    let upstream = format!("origin/{}", branch);
+    if !succeeds(folder, &["rev-parse", "--verify", "--quiet", &upstream])?
+        || !succeeds(folder, &["rev-parse", "--verify", "--quiet", "HEAD"])?
+        || succeeds(folder, &["merge-base", "--is-ancestor", &upstream, "HEAD"])?
+    {
+        return Ok(());
+    }
+    if run(folder, &["rebase", "--quiet", &upstream])?.status.success() {
+        return Ok(());
+    }
+    let files = git(folder, &["diff", "--name-only", "--diff-filter=U"])?
+        .lines()
+        .map(String::from)
+        .collect();
+    git(folder, &["rebase", "--abort"])?;
+    Err(io::Error::other(Conflict { files, report: None }))
+}
+
+pub fn fetch(folder: &Path) -> io::Result<()> {
+    git(folder, &["fetch", "--quiet", "origin"]).map(|_| ())
+}
+
+// Fast-forwards the local backup to the fetched remote branch. Local commits
+// that were never pushed make this fail instead of silently merging.
+pub fn fast_forward(folder: &Path, branch: &str) -> io::Result<()> {
+    let upstream = format!("origin/{}", branch);
+    if !succeeds(folder, &["rev-parse", "--verify", "--quiet", &upstream])? {
+        return Ok(());
+    }
+    if !succeeds(folder, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+        return git(folder, &["checkout", "--quiet", "-B", branch, &upstream]).map(|_| ());
+    }
+    if !succeeds(folder, &["merge", "--quiet", "--ff-only", &upstream])? {
+        return Err(io::Error::other(format!(
+            "the local backup has diverged from {}, run `syd backup` first",
+            upstream
+        )));
+    }
+    Ok(())
+}
+
+pub fn hostname() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "localhost".to_string())
+}
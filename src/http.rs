@@ -0,0 +1,147 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const TIMEOUT_SECONDS: &str = "10";
+
+// Tells apart the config files of concurrent requests from one process.
+static CONFIG_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// HTTP goes through curl so syd does not need a TLS stack of its own. Headers
+// such as forge tokens are passed in a config file, not on the command line.
+pub fn request(method: &str, url: &str, headers: &[&str], body: Option<&str>) -> io::Result<String> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", TIMEOUT_SECONDS, "--retry", "2"])
+        .args(["--request", method]);
+    let options: Vec<(&str, &str)> = headers.iter().map(|header| ("header", *header)).collect();
+    let secrets = (!options.is_empty()).then(|| ConfigFile::new(&options)).transpose()?;
+    if let Some(secrets) = &secrets {
+        command.arg("--config").arg(secrets.path());
+    }
+    if body.is_some() {
+        command.args(["--data-binary", "@-"]);
+    }
+    command.arg(url);
+    curl(command, body).map_err(|e| io::Error::other(format!("{} {} failed: {}", method, url, e)))
+}
+
+pub fn send_mail(url: &str, user: Option<&str>, from: &str, to: &[String], message: &str) -> io::Result<()> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--show-error", "--ssl-reqd"])
+        .args(["--max-time", TIMEOUT_SECONDS])
+        .args(["--url", url, "--mail-from", from]);
+    for recipient in to {
+        command.args(["--mail-rcpt", recipient]);
+    }
+    let secrets = user.map(|user| ConfigFile::new(&[("user", user)])).transpose()?;
+    if let Some(secrets) = &secrets {
+        command.arg("--config").arg(secrets.path());
+    }
+    command.args(["--upload-file", "-"]);
+    curl(command, Some(message))
+        .map(|_| ())
+        .map_err(|e| io::Error::other(format!("sending mail via {} failed: {}", url, e)))
+}
+
+fn curl(mut command: Command, body: Option<&str>) -> io::Result<String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(body) = body {
+        child.stdin.take().expect("piped stdin").write_all(body.as_bytes())?;
+    }
+    drop(child.stdin.take());
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Options that would show up in the process list on curl's command line, such
+// as passwords, go into a curl config file only the user can read instead. It
+// is removed again when dropped. Stdin is not free for `--config -`, it
+// carries the body.
+struct ConfigFile(PathBuf);
+
+impl ConfigFile {
+    fn new(options: &[(&str, &str)]) -> io::Result<ConfigFile> {
+        let mut text = String::new();
+        for (name, value) in options {
+            if value.contains(['\r', '\n']) {
+                return Err(io::Error::other(format!("the curl {} contains a line break", name)));
+            }
+            text.push_str(&format!("{} = \"{}\"\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        let count = CONFIG_FILES.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("syd-curl-{}-{}", process::id(), count));
+        let _ = fs::remove_dir_all(&dir);
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        let file = ConfigFile(dir);
+        OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .mode(0o600)
+            .open(file.path())?
+            .write_all(text.as_bytes())?;
+        Ok(file)
+    }
+
+    fn path(&self) -> PathBuf {
+        self.0.join("curlrc")
+    }
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+
+    #[test]
+    fn config_files_quote_values_and_are_private() {
+        let file = ConfigFile::new(&[("user", "me:pa\"ss\\word")]).unwrap();
+        let path = file.path();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "user = \"me:pa\\\"ss\\\\word\"\n");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        drop(file);
+        assert!(!path.exists());
+        assert!(ConfigFile::new(&[("header", "X: a\r\nInjected: b")]).is_err());
+    }
+
+    #[test]
+    fn request_sends_headers_from_the_config_file() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        assert_eq!(request("GET", &url, &["Authorization: Bearer \"secret\""], None).unwrap(), "ok");
+        assert!(server.join().unwrap().contains("\r\nAuthorization: Bearer \"secret\"\r\n"));
+    }
+}
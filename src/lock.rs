@@ -0,0 +1,38 @@
+// Commands that change the backup folder or $HOME hold an exclusive lock on
+// `lock` in the state directory, so a manual backup, the daemon and a restore
+// never interleave; a second one waits for the first. `list`, `status`,
+// `diff` and `stats` never take it. They stay consistent anyway because
+// writers rename finished files into the backup folder and the state
+// directory instead of rewriting them in place.
+use crate::log;
+use crate::output;
+use crate::state;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+
+const LOCK_FILE: &str = "lock";
+
+// Released when dropped.
+pub struct WriteLock {
+    _file: File,
+}
+
+pub fn write_lock() -> io::Result<WriteLock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(state::state_dir()?.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            if !output::machine_readable() {
+                eprintln!("syd: waiting for another syd run to finish");
+            }
+            log::info("waiting for another syd run to finish", &[]);
+            file.lock()?;
+        }
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    Ok(WriteLock { _file: file })
+}
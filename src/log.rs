@@ -0,0 +1,139 @@
+use crate::config::Config;
+use crate::git;
+use crate::json;
+use crate::output;
+use crate::state;
+use crate::time;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const LOG_FILE: &str = "syd.log";
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const DEFAULT_KEEP: u64 = 5;
+
+struct Settings {
+    max_size: u64,
+    max_age: Duration,
+    keep: u64,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+pub fn init(config: &Config) {
+    let settings = Settings {
+        max_size: config
+            .settings
+            .int("log.max_size")
+            .and_then(|size| u64::try_from(size).ok())
+            .unwrap_or(DEFAULT_MAX_SIZE),
+        max_age: config.settings.duration("log.max_age").unwrap_or(DEFAULT_MAX_AGE),
+        keep: config
+            .settings
+            .int("log.keep")
+            .and_then(|keep| u64::try_from(keep).ok())
+            .unwrap_or(DEFAULT_KEEP),
+    };
+    let _ = SETTINGS.set(settings);
+}
+
+// `--non-interactive` runs get every record as a JSON line on stderr instead
+// of the human-readable warnings and errors.
+pub fn info(message: &str, fields: &[(&str, String)]) {
+    report("info", message, fields);
+    write("info", message, fields);
+}
+
+pub fn warn(message: &str, fields: &[(&str, String)]) {
+    if !output::machine_readable() {
+        eprintln!("syd: warning: {}", message);
+    }
+    report("warn", message, fields);
+    write("warn", message, fields);
+}
+
+pub fn error(message: &str, fields: &[(&str, String)]) {
+    if !output::machine_readable() {
+        eprintln!("syd: {}", message);
+    }
+    report("error", message, fields);
+    write("error", message, fields);
+}
+
+fn report(level: &str, message: &str, fields: &[(&str, String)]) {
+    let mut line = vec![("level", json::string(level)), ("message", json::string(message))];
+    line.extend(fields.iter().map(|(key, value)| (*key, value.clone())));
+    output::progress("log", &line);
+}
+
+// The log file is best effort: syd must keep working on a read-only or full
+// state directory, so write errors are silently dropped.
+fn write(level: &str, message: &str, fields: &[(&str, String)]) {
+    let _ = try_write(level, message, fields);
+}
+
+fn try_write(level: &str, message: &str, fields: &[(&str, String)]) -> io::Result<()> {
+    let path = state::state_dir()?.join(LOG_FILE);
+    // `ts` is the wall clock, which rotation goes by; `time` follows a fixed
+    // clock like the rest of the output.
+    let now = time::wall_now();
+    rotate_if_needed(&path, now)?;
+
+    let mut line = vec![
+        ("time", json::string(&time::format_rfc3339(time::unix_now()))),
+        ("ts", now.to_string()),
+        ("level", json::string(level)),
+        ("host", json::string(&git::hostname())),
+        ("pid", std::process::id().to_string()),
+        ("message", json::string(message)),
+    ];
+    line.extend(fields.iter().map(|(key, value)| (*key, value.clone())));
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", json::object(&line))
+}
+
+fn rotate_if_needed(path: &Path, now: u64) -> io::Result<()> {
+    let default_settings = Settings {
+        max_size: DEFAULT_MAX_SIZE,
+        max_age: DEFAULT_MAX_AGE,
+        keep: DEFAULT_KEEP,
+    };
+    let settings = SETTINGS.get().unwrap_or(&default_settings);
+    let Ok(metadata) = fs::metadata(path) else {
+        return Ok(());
+    };
+    let too_big = metadata.len() >= settings.max_size;
+    let too_old = first_timestamp(path)
+        .map(|ts| now.saturating_sub(ts) >= settings.max_age.as_secs())
+        .unwrap_or(false);
+    if !too_big && !too_old {
+        return Ok(());
+    }
+    if settings.keep == 0 {
+        return fs::remove_file(path);
+    }
+    for index in (1..settings.keep).rev() {
+        let from = rotated(path, index);
+        if from.exists() {
+            fs::rename(from, rotated(path, index + 1))?;
+        }
+    }
+    fs::rename(path, rotated(path, 1))
+}
+
+fn rotated(path: &Path, index: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+fn first_timestamp(path: &Path) -> Option<u64> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).ok()?).read_line(&mut line).ok()?;
+    let rest = &line[line.find("\"ts\":")? + 5..];
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
@@ -1,47 +1,910 @@
-use io::Error;
-use simple_expand_tilde::*;
-use std::fs::File;
-use std::io::BufRead;
-use std::path::PathBuf;
-use std::{fs, io};
+mod backup;
+mod cache;
+mod config;
+mod conflicts;
+mod crypto;
+mod delta;
+mod doctor;
+mod drift;
+mod editor;
+mod events;
+mod exec;
+mod export;
+mod files;
+mod filter;
+mod forge;
+mod git;
+mod hash;
+mod http;
+mod json;
+mod lock;
+mod log;
+mod manifest;
+mod metadata;
+mod metrics;
+#[cfg(target_os = "linux")]
+mod mount;
+mod notifications;
+mod ops;
+mod output;
+mod owner;
+mod picker;
+mod push;
+mod recovery;
+mod restore;
+mod scrub;
+mod share;
+mod shell;
+mod state;
+mod stats;
+mod status;
+mod storage;
+mod store;
+mod time;
+mod trial;
+mod trust;
+mod undo;
+mod verify;
+mod web;
+
+use backup::BackupFlags;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: syd [--non-interactive] [--assume-yes] [--a11y] [--events-fd N] [backup [--quiet] [--no-push] [--repo NAME]|add [PATH...]|restore [--all|--preview|--accept-rewrite|--target DIR|--repo NAME|PATH...]|list|status [--check]|diff [PATH...]|drift|doctor|export --format home-manager|scrub [--text TEXT] [--repo NAME] [PATH|GLOB...]|selftest|stats [--repo [NAME]]|undo|ops [--since DURATION]|keys [list|add|remove|rotate|export-recovery|import-recovery]|mount DIR|share|try BRANCH|try --end|shell-init SHELL|verify --remote-only URL [--branch BRANCH]|daemon]";
+const DEFAULT_DAEMON_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 fn main() {
-    let backup_folder_path = PathBuf::from("~/syd/");
-    let config_path = "~/.config/syd/";
-    let config_file = "syd.conf";
-    let config = read_config_path(config_path.to_string(), config_file.to_string());
-    println!("{:?}", read_config(config));
-    create_backup_folder(backup_folder_path).unwrap()
-}
-fn read_config_path(config_path: String, config_file: String) -> PathBuf {
-    let mut config = expand_tilde(config_path).expect("Failed to expand tilde into config path");
-    config.push(PathBuf::from(config_file));
-    config
-}
-fn read_config(config:PathBuf) -> Vec<PathBuf> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let non_interactive = take_flag(&mut args, "--non-interactive");
+    let assume_yes = take_flag(&mut args, "--assume-yes");
+    let a11y = take_flag(&mut args, "--a11y");
+    match take_value(&mut args, "--events-fd").map(|fd| fd.parse()) {
+        Some(Ok(fd)) => events::init_fd(fd),
+        Some(Err(_)) => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+        None => {}
+    }
+    output::init(non_interactive, assume_yes, a11y);
+    let result = match args.first().map(String::as_str) {
+        None | Some("backup") => {
+            let mut flags = BackupFlags {
+                unattended: !output::interactive(),
+                trigger: ops::Trigger::current(),
+                ..BackupFlags::default()
+            };
+            let mut rest = args.iter().skip(1);
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--quiet" => flags.quiet = true,
+                    "--no-push" => flags.no_push = true,
+                    "--repo" if rest.next().is_some() => {}
+                    _ => {
+                        eprintln!("{}", USAGE);
+                        process::exit(2);
+                    }
+                }
+            }
+            load_config(&args).and_then(|config| backup(&config, &flags))
+        }
+        Some("add") => config::load().and_then(|config| add(&config, &args[1..])),
+        Some("restore") => load_config(&args).and_then(|config| restore(&config, &args[1..])),
+        Some("keys") => config::load().and_then(|config| keys(&config, &args[1..])),
+        Some("drift") => load_config(&args).and_then(|config| drift(&config)),
+        Some("doctor") => load_config(&args).and_then(|config| doctor(&config)),
+        Some("export") => match (args.get(1).map(String::as_str), args.get(2).map(String::as_str)) {
+            (Some("--format"), Some("home-manager")) => load_config(&args).and_then(|config| {
+                print!("{}", export::home_manager(&config)?);
+                Ok(())
+            }),
+            _ => {
+                eprintln!("Usage: syd export --format home-manager [--repo NAME]");
+                process::exit(2);
+            }
+        },
+        Some("scrub") => load_config(&args).and_then(|config| scrub(&config, &args[1..])),
+        Some("selftest") => config::load().and_then(|config| selftest(&config)),
+        Some("stats") => match args.get(1).map(String::as_str) {
+            None | Some("--repo") if args.len() <= 3 => load_config(&args).and_then(|config| stats(&config)),
+            _ => {
+                eprintln!("Usage: syd stats [--repo [NAME]]");
+                process::exit(2);
+            }
+        },
+        Some("list") => config::load().and_then(|config| list(&config)),
+        Some("status") => match args.get(1).map(String::as_str) {
+            None => config::load().and_then(|config| status(&config)),
+            Some("--check") => process::exit(status_check()),
+            Some(_) => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        },
+        Some("ops") => match (args.get(1).map(String::as_str), args.get(2)) {
+            (None, _) => ops(None),
+            (Some("--since"), Some(since)) if time::parse_duration(since).is_some() => ops(time::parse_duration(since)),
+            _ => {
+                eprintln!("Usage: syd ops [--since DURATION]");
+                process::exit(2);
+            }
+        },
+        Some("diff") => config::load().and_then(|config| diff(&config, &args[1..])),
+        Some("undo") => config::load().and_then(|config| undo(&config)),
+        #[cfg(target_os = "linux")]
+        Some("mount") => match args.get(1) {
+            Some(dir) => config::load().and_then(|config| mount::mount_history(&config, &config::expand_path(dir))),
+            None => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        },
+        Some("try") => config::load().and_then(|config| try_branch(&config, &args[1..])),
+        Some("share") => config::load().and_then(|config| share(&config)),
+        Some("shell-init") => match args.get(1).and_then(|shell| shell::snippet(shell)) {
+            Some(snippet) => {
+                print!("{}", snippet);
+                Ok(())
+            }
+            None => {
+                eprintln!("Usage: syd shell-init bash|zsh|fish");
+                process::exit(2);
+            }
+        },
+        Some("verify") => match (args.get(1).map(String::as_str), args.get(2)) {
+            (Some("--remote-only"), Some(url)) => match (args.get(3).map(String::as_str), args.get(4)) {
+                (None, _) => verify_remote(url, None),
+                (Some("--branch"), Some(branch)) => verify_remote(url, Some(branch)),
+                _ => {
+                    eprintln!("Usage: syd verify --remote-only URL [--branch BRANCH]");
+                    process::exit(2);
+                }
+            },
+            _ => {
+                eprintln!("Usage: syd verify --remote-only URL [--branch BRANCH]");
+                process::exit(2);
+            }
+        },
+        Some("daemon") => daemon(),
+        Some(push::THROTTLE_COMMAND) => push::throttle(&args[1..]),
+        Some("-h") | Some("--help") | Some("help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => {
+            eprintln!("syd: unknown command {:?}\n{}", other, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        log::error(&e.to_string(), &[]);
+        process::exit(1);
+    }
+}
+
+// Removes a global flag wherever it appears, so `syd --assume-yes restore`
+// and `syd restore --assume-yes` both work.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != before
+}
+
+// Like `take_flag`, for a flag with a value.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.remove(index);
+    match index < args.len() {
+        true => Some(args.remove(index)),
+        false => Some(String::new()),
+    }
+}
+
+// The config, narrowed down to one repo when the arguments have `--repo NAME`.
+fn load_config(args: &[String]) -> io::Result<config::Config> {
+    let config = config::load()?;
+    match args.iter().position(|arg| arg == "--repo").and_then(|index| args.get(index + 1)) {
+        Some(name) => config.select_repo(name),
+        None => Ok(config),
+    }
+}
+
+fn backup(config: &config::Config, flags: &BackupFlags) -> io::Result<()> {
+    let report = backup::run(config, flags)?;
+    if flags.quiet {
+        return Ok(());
+    }
+    if report.committed {
+        println!(
+            "Backed up {} file(s) ({} bytes){}",
+            report.files_changed,
+            report.bytes_copied,
+            if report.files_moved > 0 { format!(", {} moved", report.files_moved) } else { String::new() }
+        );
+    } else {
+        println!("Nothing to back up");
+    }
+    Ok(())
+}
+
+fn add(config: &config::Config, paths: &[String]) -> io::Result<()> {
+    let paths: Vec<_> = if paths.is_empty() {
+        if !output::interactive() {
+            eprintln!("syd add: no paths given and no terminal to pick them on\n{}", USAGE);
+            process::exit(2);
+        }
+        let candidates = config::untracked_candidates(config)?;
+        let names: Vec<String> = candidates.iter().map(|path| config::display_path(path)).collect();
+        match picker::pick("add", &names)? {
+            Some(chosen) => chosen.iter().map(|name| config::expand_path(name)).collect(),
+            None => return Ok(()),
+        }
+    } else {
+        paths.iter().map(|path| config::expand_path(path)).collect()
+    };
+    let new: Vec<_> = paths
+        .into_iter()
+        .filter(|path| !config.entries.iter().any(|entry| &entry.path == path))
+        .collect();
+    if new.is_empty() {
+        println!("Nothing to add");
+        return Ok(());
+    }
+    config::add_entries(config, &new)?;
+    for path in &new {
+        println!("Tracking {}", config::display_path(path));
+    }
+    Ok(())
+}
+
+// Without paths an interactive restore opens the picker over all tracked
+// files; `--all` (or a non-interactive run) restores everything. `--target
+// DIR` writes the files of the home directory below DIR instead.
+fn restore(config: &config::Config, args: &[String]) -> io::Result<()> {
+    log::init(config);
+    files::init(config)?;
+    let _lock = lock::write_lock()?;
     let mut paths = Vec::new();
-    let file = File::open(config).expect("Could not open file");
-    let reader = io::BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.expect("Could not read line");
-        paths.push(PathBuf::from(line));
-    }
-    paths
-}
-fn create_backup_folder(backup_folder_path: PathBuf) -> io::Result<()> {
-    let expanded_path = expand_tilde(backup_folder_path).ok_or_else(|| {
-        Error::new(
-        io::ErrorKind::NotFound,
-        "Failed to expand tilde"
-        )})?;
-    if !expanded_path.exists() {
-        fs::create_dir(&expanded_path)?;
+    let mut root = None;
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--target" => match args_iter.next() {
+                Some(dir) => root = Some(config::expand_path(dir)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "--repo" if args_iter.next().is_some() => {}
+            "--repo" => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+            arg if arg.starts_with("--") => {}
+            _ => paths.push(arg),
+        }
+    }
+    let root = root.as_deref();
+    if let Err(e) = restore::update_backup_folder(config, args.iter().any(|arg| arg == "--accept-rewrite")) {
+        ops::record("restore", ops::Trigger::current(), Err(&e));
+        return Err(e);
+    }
+    let preview = args.iter().any(|arg| arg == "--preview");
+    let all = preview || root.is_some() || args.iter().any(|arg| arg == "--all");
+    let only: Vec<_> = if paths.is_empty() && !all && output::interactive() {
+        let tracked = restore::tracked_files(config)?;
+        let names: Vec<String> = tracked.iter().map(|path| config::display_path(path)).collect();
+        match picker::pick("restore", &names)? {
+            Some(chosen) if !chosen.is_empty() => chosen.iter().map(|name| config::expand_path(name)).collect(),
+            _ => return Ok(()),
+        }
+    } else {
+        paths.iter().map(|path| config::expand_path(path)).collect()
+    };
+    if preview && !preview_restore(config, &only, root)? {
+        return Ok(());
+    }
+    let result = restore::restore_dotfiles(config, &only, root);
+    ops::record("restore", ops::Trigger::current(), result.as_ref().map(|report| report.files.as_slice()));
+    let report = result?;
+    log::info(
+        "restore finished",
+        &[
+            ("restored", report.restored.to_string()),
+            ("unchanged", report.unchanged.to_string()),
+        ],
+    );
+    if report.restored == 0 {
+        println!("Nothing to restore, {} file(s) already up to date", report.unchanged);
     } else {
-        println!("Backup folder {:?} already exist", expanded_path)
+        println!(
+            "Restored {} file(s), {} already up to date. Run `syd undo` to revert.",
+            report.restored, report.unchanged
+        );
+    }
+    Ok(())
+}
+
+// Prints the files a restore would touch grouped by directory, then asks
+// whether to go ahead. Non-interactive runs only print, unless `--assume-yes`.
+fn preview_restore(config: &config::Config, only: &[PathBuf], root: Option<&Path>) -> io::Result<bool> {
+    let preview = restore::preview(config, only, root)?;
+    let mut directory = None;
+    for (path, action, size) in &preview {
+        let parent = path.parent().map(config::display_path).unwrap_or_default();
+        if directory.as_ref() != Some(&parent) && !output::accessible() {
+            println!("{}/", parent.trim_end_matches('/'));
+            directory = Some(parent);
+        }
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let size = files::format_size(*size);
+        let table = format!("  {:<10} {:>9}  {}", action.label(), size, name);
+        let path = config::display_path(path);
+        println!("{}", output::row(table, &[("", action.label()), ("", &path), ("size", &size)]));
+    }
+    let count = |wanted| preview.iter().filter(|(_, action, _)| *action == wanted).count();
+    println!(
+        "{} to create, {} to overwrite, {} unchanged",
+        count(restore::Action::Create),
+        count(restore::Action::Overwrite),
+        count(restore::Action::Skip)
+    );
+    if count(restore::Action::Skip) == preview.len() {
+        return Ok(false);
+    }
+    output::confirm("Restore?")
+}
+
+fn share(config: &config::Config) -> io::Result<()> {
+    log::init(config);
+    let report = share::share(config)?;
+    log::info(
+        "share finished",
+        &[
+            ("files", report.files.to_string()),
+            ("redacted_lines", report.redacted_lines.to_string()),
+        ],
+    );
+    println!(
+        "Shared {} file(s) into {}{}",
+        report.files,
+        config::display_path(&share::share_folder(config)),
+        if report.pushed { " and pushed them" } else { "" }
+    );
+    if report.redacted_lines > 0 {
+        println!("Removed {} line(s) matching share.redact", report.redacted_lines);
+    }
+    Ok(())
+}
+
+fn drift(config: &config::Config) -> io::Result<()> {
+    let drifts = drift::drift(config)?;
+    for drift in &drifts {
+        println!("{}", config::display_path(&drift.path));
+        let width = drift.versions.iter().map(|version| version.host.len()).max().unwrap_or(0);
+        for version in &drift.versions {
+            let blob = match &version.blob {
+                Some(blob) => &blob[..blob.len().min(12)],
+                None => "deleted",
+            };
+            let table = format!("  {:<width$}  {}  {}", version.host, &version.date[..10], blob);
+            let labeled = [("host", version.host.as_str()), ("date", &version.date[..10]), ("blob", blob)];
+            println!("{}", output::row(table, &labeled));
+        }
+    }
+    if drifts.is_empty() {
+        println!("No drift: every machine last backed up the same version of each file");
+    } else {
+        println!("{} file(s) differ between machines", drifts.len());
+    }
+    Ok(())
+}
+
+// Lists what would be removed from history and asks before rewriting it.
+// Non-interactive runs only list, unless `--assume-yes`.
+fn scrub(config: &config::Config, args: &[String]) -> io::Result<()> {
+    log::init(config);
+    let mut request = scrub::Scrub::default();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--text" | "--repo" => match rest.next() {
+                Some(text) if arg == "--text" => request.texts.push(text.clone()),
+                Some(_) => {}
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            _ => request.paths.push(arg.clone()),
+        }
+    }
+    if request.paths.is_empty() && request.texts.is_empty() {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let _lock = lock::write_lock()?;
+    let reports = scrub::preview(config, &request)?;
+    for report in &reports {
+        let folder = config::display_path(&report.folder);
+        for path in &report.paths {
+            println!("{}: remove {} from every commit", folder, path);
+        }
+        if report.blobs > 0 {
+            println!("{}: replace the text in {} version(s) of files", folder, report.blobs);
+        }
+        for path in &report.encrypted {
+            println!("{}: {} is encrypted and was not searched for the text", folder, path);
+        }
+    }
+    if reports.iter().all(scrub::ScrubReport::is_empty) {
+        println!("Nothing in the history matches");
+        return Ok(());
+    }
+    if !output::confirm("Rewrite the history and force-push every branch and tag?")? {
+        return Ok(());
+    }
+    for report in scrub::scrub(config, &request)? {
+        if report.is_empty() {
+            continue;
+        }
+        println!(
+            "Scrubbed {}{}",
+            config::display_path(&report.folder),
+            if report.pushed { " and force-pushed it" } else { "" }
+        );
+    }
+    if reports.iter().any(|report| !report.paths.is_empty()) {
+        println!("Files still tracked are committed again by the next backup; untrack or encrypt them first");
+    }
+    if reports.iter().any(|report| report.blobs > 0) {
+        println!("Files that still contain the text are committed again once they change");
+    }
+    println!("Other machines have to run `syd restore --accept-rewrite` once");
+    Ok(())
+}
+
+// Exits 1 on warnings; checks that could not run only print.
+fn doctor(config: &config::Config) -> io::Result<()> {
+    let findings = doctor::doctor(config)?;
+    for finding in &findings {
+        println!(
+            "{}: {}: {}",
+            finding.level.label(),
+            config::display_path(&finding.folder),
+            finding.message
+        );
+    }
+    if findings.iter().any(|finding| finding.level == doctor::Level::Warning) {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Exits 1 when a live file differs from or is missing next to what the
+// backup would restore.
+fn selftest(config: &config::Config) -> io::Result<()> {
+    let report = restore::selftest(config)?;
+    for path in &report.differ {
+        println!("differs: {}", config::display_path(path));
+    }
+    for path in &report.missing {
+        println!("missing: {}", config::display_path(path));
+    }
+    println!(
+        "Restored {} file(s) from HEAD into a sandbox, {} differ from the live files, {} missing locally",
+        report.checked,
+        report.differ.len(),
+        report.missing.len()
+    );
+    log::info(
+        "selftest finished",
+        &[
+            ("checked", report.checked.to_string()),
+            ("differ", report.differ.len().to_string()),
+            ("missing", report.missing.len().to_string()),
+        ],
+    );
+    if !report.differ.is_empty() || !report.missing.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// The largest files of each repo's history, flagging those taking a tenth or
+// more of the repo, and what cleaning them up would save.
+fn stats(config: &config::Config) -> io::Result<()> {
+    for repo in stats::stats(config)? {
+        println!(
+            "{}: {} on disk, {} commit(s), {} path(s) in history",
+            config::display_path(&repo.folder),
+            files::format_size(repo.disk),
+            repo.commits,
+            repo.files.len()
+        );
+        let top = &repo.files[..repo.files.len().min(stats::TOP_OFFENDERS)];
+        let flagged = |file: &stats::FileStats| repo.disk > 0 && file.disk * 10 >= repo.disk;
+        if !output::accessible() {
+            println!("  {:>10}  {:>8}  {:>10}  path", "history", "versions", "current");
+        }
+        for file in top {
+            let current = match file.current {
+                0 => "deleted".to_string(),
+                size => files::format_size(size),
+            };
+            let share = match flagged(file) {
+                true => format!("{}% of the repo", file.disk * 100 / repo.disk),
+                false => String::new(),
+            };
+            let (history, versions) = (files::format_size(file.disk), file.versions.to_string());
+            let table = format!(
+                "  {:>10}  {:>8}  {:>10}  {}{}",
+                history,
+                versions,
+                current,
+                file.path,
+                if share.is_empty() { String::new() } else { format!("  <- {}", share) }
+            );
+            let labeled = [
+                ("", file.path.as_str()),
+                ("history", &history),
+                ("versions", &versions),
+                ("current", &current),
+                ("flagged", &share),
+            ];
+            println!("{}", output::row(table, &labeled));
+        }
+        let offenders: Vec<&stats::FileStats> = top.iter().filter(|file| flagged(file)).collect();
+        if !offenders.is_empty() {
+            let prune: u64 = offenders.iter().map(|file| file.prune_savings).sum();
+            let lfs: u64 = offenders.iter().map(|file| file.disk).sum();
+            println!(
+                "Keeping only the current version of the flagged files would save about {}, \
+                 moving them to Git LFS about {}",
+                files::format_size(prune),
+                files::format_size(lfs)
+            );
+        }
+    }
+    Ok(())
+}
+
+// Every tracked file with the host and date of its last backup.
+fn list(config: &config::Config) -> io::Result<()> {
+    let origins = status::origins(config)?;
+    let names: Vec<(String, Option<&metadata::Origin>)> = restore::tracked_files(config)?
+        .iter()
+        .map(|path| (config::display_path(path), origins.get(path)))
+        .collect();
+    let width = names.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, origin) in &names {
+        match origin {
+            Some(origin) => {
+                let table = format!("{:<width$}  {}  {}", name, origin.host, &origin.date[..10]);
+                let labeled = [("", name.as_str()), ("backed up from", &origin.host), ("on", &origin.date[..10])];
+                println!("{}", output::row(table, &labeled));
+            }
+            None => println!("{}", name),
+        }
+    }
+    Ok(())
+}
+
+fn status(config: &config::Config) -> io::Result<()> {
+    let report = status::status(config)?;
+    let origins = status::origins(config)?;
+    for (change, path) in &report.changes {
+        let origin = match origins.get(path) {
+            Some(origin) => format!(" (last backed up from {} on {})", origin.host, &origin.date[..10]),
+            None => String::new(),
+        };
+        let label = format!("{}:", change.label());
+        match output::accessible() {
+            true => println!("{} {}{}", label, config::display_path(path), origin),
+            false => println!("{:<10}{}{}", label, config::display_path(path), origin),
+        }
+    }
+    if report.unpushed > 0 {
+        println!("{} commit(s) not pushed yet", report.unpushed);
+    }
+    if report.changes.is_empty() && report.unpushed == 0 {
+        println!("Everything is backed up");
+    }
+    Ok(())
+}
+
+// Unified diffs from the backed-up version to the file in $HOME for modified
+// files, optionally only below the given paths.
+fn diff(config: &config::Config, paths: &[String]) -> io::Result<()> {
+    let only: Vec<PathBuf> = paths.iter().map(|path| config::expand_path(path)).collect();
+    let report = status::status(config)?;
+    for (change, path) in &report.changes {
+        if !only.is_empty() && !only.iter().any(|prefix| path.starts_with(prefix)) {
+            continue;
+        }
+        let name = config::display_path(path);
+        if *change != status::Change::Modified {
+            println!("{}: {}", change.label(), name);
+            continue;
+        }
+        let Some(stored) = status::stored_content(config, path)? else {
+            continue;
+        };
+        let mut command = process::Command::new("diff");
+        command.args(["-u", "--label", &format!("backup/{}", name), "--label", &name, "-"]).arg(path);
+        let output = exec::run(command, &stored)?;
+        io::stdout().write_all(&output.stdout)?;
+        // diff exits 1 when the files differ and 2 on trouble.
+        if output.status.code() == Some(2) {
+            return Err(io::Error::other(format!(
+                "diff {} failed: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Exit codes for prompts: 0 when everything is backed up, 1 when files are
+// out of sync, 3 when only commits are waiting to be pushed, 2 on errors.
+// Nothing is printed.
+fn status_check() -> i32 {
+    match config::load().and_then(|config| status::status(&config)) {
+        Ok(report) if !report.changes.is_empty() => 1,
+        Ok(report) if report.unpushed > 0 => 3,
+        Ok(_) => 0,
+        Err(_) => 2,
+    }
+}
+
+fn try_branch(config: &config::Config, args: &[String]) -> io::Result<()> {
+    log::init(config);
+    let _lock = lock::write_lock()?;
+    match args.first().map(String::as_str) {
+        Some("--end") => {
+            let (branch, reverted) = trial::end()?;
+            log::info("try ended", &[("branch", json::string(&branch))]);
+            println!("Stopped trying {}, reverted {} file(s)", branch, reverted);
+        }
+        Some(branch) if !branch.starts_with('-') => {
+            let report = trial::start(config, branch)?;
+            log::info("try started", &[("branch", json::string(branch))]);
+            println!(
+                "Trying {}: restored {} file(s), {} unchanged. Run `syd try --end` to go back.",
+                branch, report.restored, report.unchanged
+            );
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+    Ok(())
+}
+
+fn verify_remote(url: &str, branch: Option<&str>) -> io::Result<()> {
+    let report = verify::verify_remote(url, branch)?;
+    println!("{} at {}, {} commit(s)", url, &report.head[..report.head.len().min(12)], report.commits);
+    for (signer, count) in &report.signers {
+        let count = count.to_string();
+        println!("{}", output::row(format!("  {:>5}  {}", count, signer), &[("", signer), ("commits", &count)]));
+    }
+    match &report.refused {
+        Some(reason) => println!("A restore would be refused: {}", reason),
+        None => println!("The configured trust checks pass"),
+    }
+    println!("A restore would:");
+    for file in &report.files {
+        let target = config::display_path(&file.target);
+        let note = if file.encrypted { "encrypted, not compared" } else { "" };
+        let table = format!(
+            "  {:<10} {}  {}{}",
+            file.action.label(),
+            &file.blob[..12],
+            target,
+            if file.encrypted { format!(" ({})", note) } else { String::new() }
+        );
+        let labeled = [("", file.action.label()), ("", &target), ("blob", &file.blob[..12]), ("", note)];
+        println!("{}", output::row(table, &labeled));
+    }
+    Ok(())
+}
+
+fn undo(config: &config::Config) -> io::Result<()> {
+    log::init(config);
+    let _lock = lock::write_lock()?;
+    let retention = config
+        .settings
+        .duration("restore.undo_retention")
+        .unwrap_or(undo::DEFAULT_RETENTION);
+    let result = undo::undo_last(retention);
+    ops::record("undo", ops::Trigger::current(), result.as_deref());
+    let count = result?.len();
+    log::info("undo finished", &[("files", count.to_string())]);
+    println!("Reverted {} file(s) to their state before the last restore", count);
+    Ok(())
+}
+
+// Every recorded backup, restore and undo, oldest first, with `--since 7d`
+// only those of the last week.
+fn ops(since: Option<Duration>) -> io::Result<()> {
+    let since = since.map(|since| time::unix_now().saturating_sub(since.as_secs())).unwrap_or(0);
+    let records = ops::since(since)?;
+    if records.is_empty() {
+        println!("No operations recorded");
+        return Ok(());
+    }
+    for record in &records {
+        let outcome = match (&record.error, record.files.len()) {
+            (Some(error), _) => format!("failed: {}", error),
+            (None, 0) if record.runs > 1 => {
+                format!("nothing changed, {} runs until {}", record.runs, time::format_rfc3339(record.last))
+            }
+            (None, 0) => "nothing changed".to_string(),
+            (None, count) => format!("{} file(s)", count),
+        };
+        let started = time::format_rfc3339(record.started);
+        let table = format!(
+            "{}  {:<7}  {:<10}  {}  {}",
+            started,
+            record.op,
+            record.trigger.label(),
+            record.host,
+            outcome
+        );
+        let labeled = [
+            ("", record.op.as_str()),
+            ("at", &started),
+            ("from", record.trigger.label()),
+            ("on", &record.host),
+            ("", &outcome),
+        ];
+        println!("{}", output::row(table, &labeled));
+        for path in &record.files {
+            let name = config::display_path(path);
+            println!("{}", output::row(format!("    {}", name), &[("file", &name)]));
+        }
+    }
+    Ok(())
+}
+
+fn keys(config: &config::Config, args: &[String]) -> io::Result<()> {
+    log::init(config);
+    let _lock = lock::write_lock()?;
+    if args.first().map(String::as_str) == Some("import-recovery") {
+        let code = match args.get(1).map(String::as_str) {
+            None | Some("-") => io::read_to_string(io::stdin())?,
+            Some(file) => fs::read_to_string(file)?,
+        };
+        recovery::import(config, &code)?;
+        log::info("recovery code imported", &[]);
+        println!("Imported the recovery code, encrypted files can be restored now");
+        return Ok(());
+    }
+    restore::update_backup_folder(config, false)?;
+    let folder = config.backup_folder();
+    let mut recipients = crypto::recipients(&folder)?;
+    let host = git::hostname();
+    let (message, done) = match (args.first().map(String::as_str), args.get(1)) {
+        (None, _) | (Some("list"), _) => {
+            for recipient in &recipients {
+                println!("{}", recipient);
+            }
+            return Ok(());
+        }
+        (Some("add"), Some(recipient)) => {
+            if recipients.contains(recipient) {
+                println!("{} is already a recipient", recipient);
+                return Ok(());
+            }
+            recipients.push(recipient.clone());
+            (format!("Add encryption recipient {} from {}", recipient, host), format!("Added {}", recipient))
+        }
+        (Some("remove"), Some(recipient)) => {
+            if !recipients.contains(recipient) {
+                return Err(io::Error::other(format!("{} is not a recipient", recipient)));
+            }
+            recipients.retain(|r| r != recipient);
+            (
+                format!("Remove encryption recipient {} from {}", recipient, host),
+                format!(
+                    "Removed {}. They can still decrypt old commits, rotate the secrets themselves if that matters",
+                    recipient
+                ),
+            )
+        }
+        (Some("export-recovery"), None) => {
+            let (key, count) = recovery::export(config)?;
+            log::info(
+                "recovery recipient added",
+                &[("recipient", json::string(&key.recipient)), ("files", count.to_string())],
+            );
+            print!("{}", key.code);
+            eprintln!("Added recovery recipient {}, re-encrypted {} file(s)", key.recipient, count);
+            return Ok(());
+        }
+        (Some("rotate"), None) => (
+            format!("Re-encrypt files for current recipients from {}", host),
+            "Rotated".to_string(),
+        ),
+        _ => {
+            eprintln!(
+                "Usage: syd keys [list|add RECIPIENT|remove RECIPIENT|rotate|export-recovery|import-recovery [FILE]]"
+            );
+            process::exit(2);
+        }
+    };
+    let count = crypto::change_recipients(config, &recipients, &message)?;
+    log::info(&message, &[("files", count.to_string())]);
+    println!("{}, re-encrypted {} file(s)", done, count);
+    Ok(())
+}
+
+// The config is reloaded on every iteration so edits apply without a restart.
+fn daemon() -> io::Result<()> {
+    if let Some(address) = config::load()?.settings.str("daemon.web") {
+        web::serve(address)?;
+    }
+    loop {
+        let interval = match config::load() {
+            Ok(config) => {
+                let mode = restore_only(&config);
+                let result = match mode {
+                    Ok(true) => pull_and_restore(&config),
+                    Ok(false) => {
+                        let flags = BackupFlags {
+                            unattended: true,
+                            trigger: ops::Trigger::Daemon,
+                            ..BackupFlags::default()
+                        };
+                        backup(&config, &flags)
+                    }
+                    Err(ref e) => Err(io::Error::other(e.to_string())),
+                };
+                if let Err(e) = result {
+                    log::error(&e.to_string(), &[]);
+                }
+                let interval = config
+                    .settings
+                    .duration("daemon.interval")
+                    .unwrap_or(DEFAULT_DAEMON_INTERVAL);
+                // Wake up when the next push window opens to flush queued pushes.
+                match push::until_window(&config) {
+                    Ok(wait) if !wait.is_zero() && matches!(mode, Ok(false)) => interval.min(wait),
+                    _ => interval,
+                }
+            }
+            Err(e) => {
+                log::error(&e.to_string(), &[]);
+                DEFAULT_DAEMON_INTERVAL
+            }
+        };
+        thread::sleep(interval);
+    }
+}
+
+// `daemon.mode = "restore"` turns the daemon into a pull-only agent for
+// machines that consume the repo but never contribute to it.
+fn restore_only(config: &config::Config) -> io::Result<bool> {
+    match config.settings.str("daemon.mode").unwrap_or("backup") {
+        "backup" => Ok(false),
+        "restore" => Ok(true),
+        other => Err(io::Error::other(format!("unknown daemon.mode {:?}", other))),
+    }
+}
+
+fn pull_and_restore(config: &config::Config) -> io::Result<()> {
+    let report = restore::pull_and_restore(config, &[], ops::Trigger::Daemon)?;
+    if report.restored > 0 {
+        println!("Restored {} file(s), {} already up to date", report.restored, report.unchanged);
     }
     Ok(())
 }
-fn backup_dotfiles() {}
-fn restore_dotfiles() {}
-fn create_local_repo() {}
-fn push_to_git() {}
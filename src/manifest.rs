@@ -0,0 +1,172 @@
+// `.syd-manifest.toml` in the backup repo describes how the repo is laid out:
+// the layout version, where each entry's files live and how they are stored,
+// and the syd version that wrote it. Restore maps entries back through it, so
+// files are found even when the repo was written with other settings, and it
+// refuses repos written in a layout this syd does not know.
+use crate::config::{self, Config, Entry, Value};
+use crate::files;
+use crate::json;
+use crate::log;
+use crate::metadata;
+use crate::storage::{self, Storage};
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+pub const MANIFEST_FILE: &str = ".syd-manifest.toml";
+// 1: files below $HOME by their relative path, everything else under `_root/`.
+// Repos from before the manifest existed use this layout as well.
+pub const LAYOUT_VERSION: i64 = 1;
+
+pub struct Manifest {
+    pub layout: i64,
+    pub syd_version: Option<String>,
+    pub files: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn load(folder: &Path) -> io::Result<Option<Manifest>> {
+        let path = folder.join(MANIFEST_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (settings, mut files, _) = config::parse(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        // The manifest comes from the remote: a record pointing outside the
+        // repo would have restores read, and backups commit, any local file.
+        files.retain(|file| {
+            let repo_path = file.repo_path();
+            let inside = repo_path.components().next().is_some()
+                && repo_path.components().all(|component| matches!(component, Component::Normal(_)));
+            if !inside {
+                log::warn(
+                    &format!(
+                        "ignoring the manifest record for {}: its repo path {} leaves the repo",
+                        file.path.display(),
+                        repo_path.display()
+                    ),
+                    &[("repo_path", json::string(&repo_path.to_string_lossy()))],
+                );
+            }
+            inside
+        });
+        let manifest = Manifest {
+            layout: settings.int("layout").unwrap_or(LAYOUT_VERSION),
+            syd_version: settings.str("syd_version").map(String::from),
+            files,
+        };
+        if manifest.layout > LAYOUT_VERSION {
+            return Err(io::Error::other(format!(
+                "{} uses layout {} (written by syd {}), this syd {} only knows layout {}; please upgrade",
+                folder.display(),
+                manifest.layout,
+                manifest.syd_version.as_deref().unwrap_or("unknown"),
+                env!("CARGO_PKG_VERSION"),
+                LAYOUT_VERSION
+            )));
+        }
+        Ok(Some(manifest))
+    }
+
+    // The entry as it was stored: the repo path and storage recorded in the
+    // manifest win over the current config, which may have changed since.
+    pub fn resolve(&self, entry: &Entry) -> Entry {
+        let mut entry = entry.clone();
+        let Some(stored) = self.files.iter().find(|file| file.path == entry.path) else {
+            return entry;
+        };
+        if let Some(repo_path) = stored.options.str("repo_path") {
+            entry.options.insert("repo_path".to_string(), Value::Str(repo_path.to_string()));
+        }
+        if let Some(storage) = stored.options.str("storage") {
+            entry.options.insert("encrypt".to_string(), Value::Bool(storage == "encrypted"));
+            entry.options.insert("delta".to_string(), Value::Bool(storage == "delta"));
+        }
+        if let Some(preserve_mtime) = stored.options.bool("preserve_mtime") {
+            entry.options.insert("preserve_mtime".to_string(), Value::Bool(preserve_mtime));
+        }
+        entry
+    }
+}
+
+// Rewrites the manifest for `config`'s entries when it changed. Records of
+// other machines' entries are kept as long as their files are in the repo.
+pub fn write(config: &Config, folder: &Path) -> io::Result<()> {
+    // Never downgrade a repo a newer syd has written to.
+    let previous = Manifest::load(folder)?;
+    let mut entries = config.entries.clone();
+    if let Some(previous) = &previous {
+        for file in &previous.files {
+            if entries.iter().any(|entry| entry.path == file.path) {
+                continue;
+            }
+            let file = previous.resolve(file);
+            if !storage::stored_files(config, &file, folder)?.is_empty() {
+                entries.push(file);
+            }
+        }
+    }
+    let mut text = format!(
+        "# Written by syd on every backup, do not edit.\nlayout = {}\nsyd_version = {}\n",
+        LAYOUT_VERSION,
+        json::string(env!("CARGO_PKG_VERSION"))
+    );
+    for entry in &entries {
+        let storage = if entry.options.str("submodule").is_some() {
+            "submodule"
+        } else {
+            match storage::storage(entry) {
+                Storage::Plain => "plain",
+                Storage::Encrypted => "encrypted",
+                Storage::Delta => "delta",
+            }
+        };
+        text.push_str(&format!(
+            "\n[[files]]\npath = {}\nrepo_path = {}\nstorage = \"{}\"\npreserve_mtime = {}\n",
+            json::string(&config::display_path(&entry.path)),
+            json::string(&entry.repo_path().to_string_lossy()),
+            storage,
+            metadata::preserve_mtime(config, entry)
+        ));
+    }
+    let path = folder.join(MANIFEST_FILE);
+    if fs::read_to_string(&path).ok().as_deref() == Some(text.as_str()) {
+        return Ok(());
+    }
+    files::replace(&path, text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_leaving_the_repo_are_ignored() {
+        let folder = std::env::temp_dir().join(format!("syd-manifest-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let records = [
+            ("/tmp/syd-a", "\"a\""),
+            ("/tmp/syd-b", "\"/home/me/.ssh/id_rsa\""),
+            ("/tmp/syd-c", "\"../../.ssh/id_rsa\""),
+            ("/tmp/syd-d", "\"dir/../../x\""),
+            ("/tmp/syd-e", "\"nested/file\""),
+        ];
+        let mut text = String::from("layout = 1\n");
+        for (path, repo_path) in records {
+            text.push_str(&format!("\n[[files]]\npath = \"{}\"\nrepo_path = {}\n", path, repo_path));
+        }
+        fs::write(folder.join(MANIFEST_FILE), text).unwrap();
+        let manifest = Manifest::load(&folder);
+        fs::remove_dir_all(&folder).unwrap();
+        let kept: Vec<String> = manifest
+            .unwrap()
+            .unwrap()
+            .files
+            .iter()
+            .map(|file| file.repo_path().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(kept, ["a", "nested/file"]);
+    }
+}
@@ -0,0 +1,160 @@
+// File modification times recorded in the backup repo, for tools that key
+// caches off mtimes. Opt-in through `files.preserve_mtime` or a per-entry
+// `preserve_mtime` option. Also which host last changed each file, taken from
+// the `Syd-Host` trailer of backup commits.
+use crate::config::{Config, Entry};
+use crate::files;
+use crate::git;
+use crate::time;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const MTIMES_FILE: &str = ".syd-mtimes";
+
+pub fn preserve_mtime(config: &Config, entry: &Entry) -> bool {
+    entry
+        .options
+        .bool("preserve_mtime")
+        .or_else(|| config.settings.bool("files.preserve_mtime"))
+        .unwrap_or(false)
+}
+
+// One `secs.nanos<TAB>path` line per file, keyed by its path in the repo.
+pub struct Mtimes {
+    path: PathBuf,
+    times: BTreeMap<String, String>,
+    changed: bool,
+}
+
+impl Mtimes {
+    pub fn load(folder: &Path) -> io::Result<Mtimes> {
+        let path = folder.join(MTIMES_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let times = text
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(mtime, file)| (file.to_string(), mtime.to_string()))
+            .collect();
+        Ok(Mtimes {
+            path,
+            times,
+            changed: false,
+        })
+    }
+
+    pub fn get(&self, repo_file: &Path) -> Option<SystemTime> {
+        time::parse_mtime(self.times.get(&repo_file.to_string_lossy().into_owned())?)
+    }
+
+    pub fn set(&mut self, repo_file: &Path, mtime: SystemTime) {
+        let value = time::format_mtime(mtime);
+        let key = repo_file.to_string_lossy().into_owned();
+        if self.times.get(&key) != Some(&value) {
+            self.times.insert(key, value);
+            self.changed = true;
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let mut text = String::new();
+        for (file, mtime) in &self.times {
+            text.push_str(&format!("{}\t{}\n", mtime, file));
+        }
+        files::replace(&self.path, text.as_bytes())
+    }
+}
+
+pub const HOST_TRAILER: &str = "Syd-Host";
+
+#[derive(Clone, Debug)]
+pub struct Origin {
+    pub host: String,
+    // Committer date of the last commit that changed the file, RFC 3339.
+    pub date: String,
+}
+
+// The last commit touching each repo file, from one walk over the history.
+pub struct Origins {
+    // Repo path to the commit's position in the log (0 is newest) and origin.
+    files: BTreeMap<String, (usize, Origin)>,
+}
+
+impl Origins {
+    pub fn load(folder: &Path) -> io::Result<Origins> {
+        let mut files = BTreeMap::new();
+        if !git::succeeds(folder, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+            return Ok(Origins { files });
+        }
+        for (index, commit) in history(folder, &["HEAD"])?.into_iter().enumerate() {
+            let origin = Origin {
+                host: commit.host,
+                date: commit.date,
+            };
+            for name in commit.files {
+                files.entry(name).or_insert_with(|| (index, origin.clone()));
+            }
+        }
+        Ok(Origins { files })
+    }
+
+    // The newest origin among the repo files storing one home file, given
+    // relative to the repo.
+    pub fn get(&self, repo_files: &[PathBuf]) -> Option<&Origin> {
+        repo_files
+            .iter()
+            .filter_map(|file| self.files.get(&file.to_string_lossy().into_owned()))
+            .min_by_key(|(index, _)| *index)
+            .map(|(_, origin)| origin)
+    }
+}
+
+pub struct Commit {
+    pub hash: String,
+    pub date: String,
+    pub host: String,
+    // Repo paths the commit added, changed or deleted.
+    pub files: Vec<String>,
+}
+
+// The commits reachable from `revisions`, newest first.
+pub fn history(folder: &Path, revisions: &[&str]) -> io::Result<Vec<Commit>> {
+    let format = format!("--format=%x1e%H%x1f%cI%x1f%(trailers:key={},valueonly,separator=)%x1f%s", HOST_TRAILER);
+    let mut args = vec!["log", "--no-renames", "--name-only", &format];
+    args.extend(revisions);
+    let log = git::git(folder, &args)?;
+    let mut commits = Vec::new();
+    for commit in log.split('\x1e').skip(1) {
+        let (header, names) = commit.split_once('\n').unwrap_or((commit, ""));
+        let mut fields = header.splitn(4, '\x1f');
+        let hash = fields.next().unwrap_or_default().to_string();
+        let date = fields.next().unwrap_or_default().to_string();
+        let trailer = fields.next().unwrap_or_default().trim();
+        let subject = fields.next().unwrap_or_default();
+        commits.push(Commit {
+            hash,
+            date,
+            host: if trailer.is_empty() { host_from_subject(subject) } else { trailer.to_string() },
+            files: names.lines().filter(|name| !name.is_empty()).map(String::from).collect(),
+        });
+    }
+    Ok(commits)
+}
+
+// Commits from before the trailer say "Backup from <host>: ...".
+fn host_from_subject(subject: &str) -> String {
+    subject
+        .split_once(" from ")
+        .map(|(_, rest)| rest.split([':', ' ']).next().unwrap_or_default().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
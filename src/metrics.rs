@@ -0,0 +1,73 @@
+use crate::backup::BackupReport;
+use crate::config::{expand_path, Config};
+use crate::state;
+use crate::time;
+use std::fs;
+use std::io;
+
+// Writes a node_exporter textfile-collector file after every run. The file
+// is replaced atomically so the collector never scrapes a partial write.
+pub fn write_textfile(config: &Config, result: &io::Result<BackupReport>) -> io::Result<()> {
+    let Some(path) = config.settings.str("metrics.textfile") else {
+        return Ok(());
+    };
+    let path = expand_path(path);
+    let now = time::unix_now();
+
+    let mut values = state::load("metrics")?;
+    let field = |values: &std::collections::BTreeMap<String, String>, key: &str| {
+        values.get(key).and_then(|v| v.parse::<u64>().ok()).unwrap_or(0)
+    };
+    let mut last_success = field(&values, "last_success");
+    let mut failures = field(&values, "failures_total");
+    match result {
+        Ok(_) => last_success = now,
+        Err(_) => failures += 1,
+    }
+    values.insert("last_success".to_string(), last_success.to_string());
+    values.insert("failures_total".to_string(), failures.to_string());
+    state::save("metrics", &values)?;
+
+    let (files_changed, bytes_copied, push_duration) = match result {
+        Ok(report) => (
+            report.files_changed,
+            report.bytes_copied,
+            report.push_duration.map(|d| d.as_secs_f64()).unwrap_or(0.0),
+        ),
+        Err(_) => (0, 0, 0.0),
+    };
+
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+    };
+    metric("syd_last_run_timestamp_seconds", "gauge", "Unix time of the last backup run.", now.to_string());
+    metric(
+        "syd_last_run_success",
+        "gauge",
+        "Whether the last backup run succeeded.",
+        u8::from(result.is_ok()).to_string(),
+    );
+    metric(
+        "syd_last_success_timestamp_seconds",
+        "gauge",
+        "Unix time of the last successful backup run.",
+        last_success.to_string(),
+    );
+    metric("syd_files_changed", "gauge", "Files changed by the last backup run.", files_changed.to_string());
+    metric("syd_bytes_copied", "gauge", "Bytes copied by the last backup run.", bytes_copied.to_string());
+    metric(
+        "syd_push_duration_seconds",
+        "gauge",
+        "Duration of the last push to the remote.",
+        format!("{:.3}", push_duration),
+    );
+    metric("syd_failures_total", "counter", "Number of failed backup runs.", failures.to_string());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("prom.tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(tmp, path)
+}
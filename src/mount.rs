@@ -0,0 +1,878 @@
+// A read-only FUSE filesystem over the backup history. The kernel protocol
+// is spoken directly on /dev/fuse, so no libfuse is needed: as root the
+// filesystem is mounted with mount(2), otherwise through fusermount3.
+//
+// The root lists `HEAD` and one directory per day with commits (the last
+// commit of that day). Any other revision git understands, such as `HEAD~5`
+// or a tag, can be looked up by name even though it is not listed. Encrypted
+// and delta copies show up under their plain names and are decrypted or have
+// their delta applied as restore would, going by the manifest's entries.
+use crate::config::{Config, Entry};
+use crate::git;
+use crate::log;
+use crate::manifest::Manifest;
+use crate::storage::{self, Storage};
+use libc::{c_int, c_void, EBADF, EINTR, EIO, ENODEV, ENOENT, ENOSYS, ENOTDIR, EROFS};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_READLINK: u32 = 5;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+const WRITE_OPCODES: [u32; 11] = [4, 8, 9, 10, 11, 12, 13, 16, 21, 24, 35];
+
+const IN_HEADER_LEN: usize = 40;
+const MAX_WRITE: usize = 128 * 1024;
+const FOPEN_KEEP_CACHE: u32 = 2;
+const ROOT: u64 = 1;
+// Revisions such as HEAD move, history below them never changes.
+const ROOT_TTL: u64 = 1;
+const SNAPSHOT_TTL: u64 = 3600;
+
+static MOUNTPOINT: OnceLock<CString> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Root,
+    Dir,
+    File { executable: bool },
+    Symlink,
+}
+
+struct Node {
+    kind: Kind,
+    // The stored path in the repo, which the entries are matched against.
+    path: String,
+    object: String,
+    storage: Storage,
+    // The delta blob that goes with a delta entry's base.
+    delta: Option<String>,
+    // Unknown for encrypted and delta copies until they are first read.
+    size: Option<u64>,
+    mtime: u64,
+    ttl: u64,
+    children: Option<Vec<(String, u64)>>,
+}
+
+struct HistoryFs {
+    config: Config,
+    repo: std::path::PathBuf,
+    entries: Vec<Entry>,
+    nodes: Vec<Node>,
+    names: HashMap<(u64, String), u64>,
+    open: HashMap<u64, Vec<u8>>,
+    next_fh: u64,
+    uid: u32,
+    gid: u32,
+}
+
+pub fn mount_history(config: &Config, mountpoint: &Path) -> io::Result<()> {
+    let repo = config.backup_folder();
+    if !repo.join(".git").exists() {
+        return Err(io::Error::other(format!("{} is not a backup repository", repo.display())));
+    }
+    let mut fs = HistoryFs::new(config)?;
+    fs::create_dir_all(mountpoint)?;
+    let mountpoint = fs::canonicalize(mountpoint)?;
+    let (device, _fusermount_socket) = open_device(&mountpoint)?;
+    println!(
+        "Mounted backup history at {}, press Ctrl-C or unmount it to stop",
+        mountpoint.display()
+    );
+    fs.serve(device)
+}
+
+fn open_device(mountpoint: &Path) -> io::Result<(File, Option<UnixStream>)> {
+    let target = CString::new(mountpoint.as_os_str().as_bytes())?;
+    if unsafe { libc::getuid() } != 0 {
+        return fusermount(mountpoint).map(|(device, socket)| (device, Some(socket)));
+    }
+    let device = OpenOptions::new().read(true).write(true).open("/dev/fuse")?;
+    let data = CString::new(format!(
+        "fd={},rootmode=40000,user_id=0,group_id=0",
+        device.as_raw_fd()
+    ))?;
+    let result = unsafe {
+        libc::mount(
+            c"syd".as_ptr(),
+            target.as_ptr(),
+            c"fuse.syd".as_ptr(),
+            libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+            data.as_ptr() as *const c_void,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let _ = MOUNTPOINT.set(target);
+    unsafe {
+        libc::signal(libc::SIGINT, on_signal as *const () as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_signal as *const () as libc::sighandler_t);
+    }
+    Ok((device, None))
+}
+
+// Detaching the mount makes the pending read on /dev/fuse fail with ENODEV,
+// which ends the request loop normally.
+extern "C" fn on_signal(_signum: c_int) {
+    if let Some(target) = MOUNTPOINT.get() {
+        unsafe {
+            libc::umount2(target.as_ptr(), libc::MNT_DETACH);
+        }
+    }
+}
+
+// fusermount3 mounts on behalf of unprivileged users and hands the device
+// back over a socket. With auto_unmount it unmounts when that socket closes,
+// so the socket has to stay open while the filesystem is served.
+fn fusermount(mountpoint: &Path) -> io::Result<(File, UnixStream)> {
+    let (ours, theirs) = UnixStream::pair()?;
+    let their_fd = theirs.as_raw_fd();
+    let mut last_error = None;
+    for program in ["fusermount3", "fusermount"] {
+        let mut command = Command::new(program);
+        command
+            .args(["-o", "ro,nosuid,nodev,fsname=syd,subtype=syd,auto_unmount", "--"])
+            .arg(mountpoint)
+            .env("_FUSE_COMMFD", their_fd.to_string());
+        unsafe {
+            command.pre_exec(move || {
+                libc::fcntl(their_fd, libc::F_SETFD, 0);
+                Ok(())
+            });
+        }
+        match command.spawn() {
+            Ok(_) => {
+                drop(theirs);
+                let device = receive_fd(&ours)?;
+                return Ok((device, ours));
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(io::Error::other(format!(
+        "mounting needs fusermount3 when not running as root: {}",
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    )))
+}
+
+fn receive_fd(socket: &UnixStream) -> io::Result<File> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut c_void,
+        iov_len: 1,
+    };
+    // Room for one cmsghdr with a descriptor, aligned for it.
+    let mut control = [0u64; 8];
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut c_void;
+    message.msg_controllen = std::mem::size_of_val(&control) as _;
+    let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, 0) };
+    let header = if received > 0 { unsafe { libc::CMSG_FIRSTHDR(&message) } } else { std::ptr::null_mut() };
+    let passed = |header: *mut libc::cmsghdr| unsafe { ((*header).cmsg_level, (*header).cmsg_type) };
+    if header.is_null() || passed(header) != (libc::SOL_SOCKET, libc::SCM_RIGHTS) {
+        return Err(io::Error::other("fusermount3 did not pass a FUSE device"));
+    }
+    let fd = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const c_int) };
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+impl HistoryFs {
+    // The configured entries of the default repo as the manifest recorded them,
+    // and the entries only other machines back up.
+    fn new(config: &Config) -> io::Result<HistoryFs> {
+        let repo = config.backup_folder();
+        let manifest = Manifest::load(&repo)?;
+        let mut entries: Vec<Entry> = config.entries.iter().filter(|entry| entry.repo().is_none()).cloned().collect();
+        if let Some(manifest) = &manifest {
+            entries = entries.iter().map(|entry| manifest.resolve(entry)).collect();
+            for file in &manifest.files {
+                if !entries.iter().any(|entry| entry.path == file.path) {
+                    entries.push(manifest.resolve(file));
+                }
+            }
+        }
+        Ok(HistoryFs {
+            config: config.clone(),
+            repo,
+            entries,
+            nodes: vec![Node {
+                kind: Kind::Root,
+                ..snapshot_node(String::new(), 0)
+            }],
+            names: HashMap::new(),
+            open: HashMap::new(),
+            next_fh: 1,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        })
+    }
+
+    fn serve(&mut self, mut device: File) -> io::Result<()> {
+        let mut buffer = vec![0u8; MAX_WRITE + 64 * 1024];
+        loop {
+            let length = match device.read(&mut buffer) {
+                Ok(length) => length,
+                Err(e) => match e.raw_os_error() {
+                    Some(ENODEV) => return Ok(()),
+                    Some(ENOENT) | Some(EINTR) => continue,
+                    _ => return Err(e),
+                },
+            };
+            if length < IN_HEADER_LEN {
+                return Err(io::Error::other("short FUSE request"));
+            }
+            let opcode = read_u32(&buffer, 4);
+            let unique = read_u64(&buffer, 8);
+            let nodeid = read_u64(&buffer, 16);
+            let body = &buffer[IN_HEADER_LEN..length];
+            if matches!(opcode, FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT) {
+                continue;
+            }
+            let result = self.handle(opcode, nodeid, body);
+            let (error, payload) = match result {
+                Ok(payload) => (0, payload),
+                Err(errno) => (-errno, Vec::new()),
+            };
+            let mut reply = Vec::with_capacity(16 + payload.len());
+            reply.extend_from_slice(&((16 + payload.len()) as u32).to_ne_bytes());
+            reply.extend_from_slice(&error.to_ne_bytes());
+            reply.extend_from_slice(&unique.to_ne_bytes());
+            reply.extend_from_slice(&payload);
+            match device.write_all(&reply) {
+                Err(e) if e.raw_os_error() == Some(ENOENT) => {}
+                Err(e) if e.raw_os_error() == Some(ENODEV) => return Ok(()),
+                other => other?,
+            }
+            if opcode == FUSE_DESTROY {
+                return Ok(());
+            }
+        }
+    }
+
+    fn handle(&mut self, opcode: u32, nodeid: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        match opcode {
+            FUSE_INIT => Ok(init_reply(body)),
+            FUSE_LOOKUP => {
+                let name = body.split(|b| *b == 0).next().unwrap_or_default();
+                let name = String::from_utf8_lossy(name).into_owned();
+                let ino = self.lookup(nodeid, &name)?;
+                self.measure(ino)?;
+                Ok(self.entry_out(ino))
+            }
+            FUSE_GETATTR => {
+                self.node(nodeid)?;
+                self.measure(nodeid)?;
+                let mut out = Vec::new();
+                push_u64(&mut out, self.ttl(nodeid));
+                push_u32(&mut out, 0);
+                push_u32(&mut out, 0);
+                out.extend_from_slice(&self.attr(nodeid));
+                Ok(out)
+            }
+            FUSE_READLINK => {
+                let node = self.node(nodeid)?;
+                if node.kind != Kind::Symlink {
+                    return Err(EIO);
+                }
+                self.blob(&node.object.clone())
+            }
+            FUSE_OPEN => {
+                let flags = read_u32(body, 0);
+                if flags & 3 != 0 {
+                    return Err(EROFS);
+                }
+                if !matches!(self.node(nodeid)?.kind, Kind::File { .. }) {
+                    return Err(EIO);
+                }
+                let data = self.content(nodeid)?;
+                let fh = self.next_fh;
+                self.next_fh += 1;
+                self.open.insert(fh, data);
+                Ok(open_out(fh, FOPEN_KEEP_CACHE))
+            }
+            FUSE_READ => {
+                let fh = read_u64(body, 0);
+                let offset = read_u64(body, 8) as usize;
+                let size = read_u32(body, 16) as usize;
+                let data = self.open.get(&fh).ok_or(EBADF)?;
+                let start = offset.min(data.len());
+                let end = (offset + size).min(data.len());
+                Ok(data[start..end].to_vec())
+            }
+            FUSE_RELEASE => {
+                self.open.remove(&read_u64(body, 0));
+                Ok(Vec::new())
+            }
+            FUSE_OPENDIR => match self.node(nodeid)?.kind {
+                Kind::Root | Kind::Dir => Ok(open_out(0, 0)),
+                _ => Err(ENOTDIR),
+            },
+            FUSE_READDIR => {
+                let offset = read_u64(body, 8) as usize;
+                let size = read_u32(body, 16) as usize;
+                self.readdir(nodeid, offset, size)
+            }
+            FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_DESTROY => Ok(Vec::new()),
+            FUSE_STATFS => {
+                let mut out = vec![0u8; 40];
+                push_u32(&mut out, 4096);
+                push_u32(&mut out, 255);
+                push_u32(&mut out, 4096);
+                out.resize(80, 0);
+                Ok(out)
+            }
+            opcode if WRITE_OPCODES.contains(&opcode) => Err(EROFS),
+            _ => Err(ENOSYS),
+        }
+    }
+
+    fn node(&self, ino: u64) -> Result<&Node, i32> {
+        self.nodes.get((ino as usize).wrapping_sub(1)).ok_or(ENOENT)
+    }
+
+    fn ttl(&self, ino: u64) -> u64 {
+        self.nodes[ino as usize - 1].ttl
+    }
+
+    fn insert(&mut self, parent: u64, name: &str, mut node: Node) -> u64 {
+        node.ttl = if parent == ROOT { ROOT_TTL } else { SNAPSHOT_TTL };
+        if let Some(&ino) = self.names.get(&(parent, name.to_string())) {
+            let existing = &mut self.nodes[ino as usize - 1];
+            if existing.object != node.object {
+                *existing = node;
+            }
+            return ino;
+        }
+        self.nodes.push(node);
+        let ino = self.nodes.len() as u64;
+        self.names.insert((parent, name.to_string()), ino);
+        ino
+    }
+
+    fn lookup(&mut self, parent: u64, name: &str) -> Result<u64, i32> {
+        if self.node(parent)?.kind == Kind::Root {
+            let (tree, mtime) = self.resolve_snapshot(name).ok_or(ENOENT)?;
+            return Ok(self.insert(ROOT, name, snapshot_node(tree, mtime)));
+        }
+        self.children(parent)?
+            .into_iter()
+            .find(|(child, _)| child == name)
+            .map(|(_, ino)| ino)
+            .ok_or(ENOENT)
+    }
+
+    fn children(&mut self, ino: u64) -> Result<Vec<(String, u64)>, i32> {
+        let node = self.node(ino)?;
+        if let Some(children) = &node.children {
+            if node.kind != Kind::Root {
+                return Ok(children.clone());
+            }
+        }
+        let children = match node.kind {
+            Kind::Root => {
+                let mut children = Vec::new();
+                for (name, tree, mtime) in self.list_snapshots() {
+                    let child = self.insert(ROOT, &name, snapshot_node(tree, mtime));
+                    children.push((name, child));
+                }
+                children
+            }
+            Kind::Dir => {
+                let (path, object, mtime) = (node.path.clone(), node.object.clone(), node.mtime);
+                let mut children = Vec::new();
+                for (name, child) in self.list_tree(&path, &object, mtime) {
+                    let child = self.insert(ino, &name, child);
+                    children.push((name, child));
+                }
+                children
+            }
+            _ => return Err(ENOTDIR),
+        };
+        self.nodes[ino as usize - 1].children = Some(children.clone());
+        Ok(children)
+    }
+
+    fn readdir(&mut self, ino: u64, offset: usize, size: usize) -> Result<Vec<u8>, i32> {
+        let mut entries = vec![(".".to_string(), ino), ("..".to_string(), ROOT)];
+        entries.extend(self.children(ino)?);
+        let mut out = Vec::new();
+        for (index, (name, child)) in entries.iter().enumerate().skip(offset) {
+            let kind = match self.node(*child)?.kind {
+                Kind::Root | Kind::Dir => 4,
+                Kind::File { .. } => 8,
+                Kind::Symlink => 10,
+            };
+            let record = 24 + name.len();
+            let padded = (record + 7) & !7;
+            if out.len() + padded > size {
+                break;
+            }
+            push_u64(&mut out, *child);
+            push_u64(&mut out, index as u64 + 1);
+            push_u32(&mut out, name.len() as u32);
+            push_u32(&mut out, kind);
+            out.extend_from_slice(name.as_bytes());
+            out.resize(out.len() + padded - record, 0);
+        }
+        Ok(out)
+    }
+
+    fn entry_out(&self, ino: u64) -> Vec<u8> {
+        let ttl = self.ttl(ino);
+        let mut out = Vec::new();
+        push_u64(&mut out, ino);
+        push_u64(&mut out, 0);
+        push_u64(&mut out, ttl);
+        push_u64(&mut out, ttl);
+        push_u32(&mut out, 0);
+        push_u32(&mut out, 0);
+        out.extend_from_slice(&self.attr(ino));
+        out
+    }
+
+    fn attr(&self, ino: u64) -> Vec<u8> {
+        let node = &self.nodes[ino as usize - 1];
+        let (mode, nlink) = match node.kind {
+            Kind::Root | Kind::Dir => (0o040555, 2),
+            Kind::File { executable: true } => (0o100555, 1),
+            Kind::File { executable: false } => (0o100444, 1),
+            Kind::Symlink => (0o120777, 1),
+        };
+        let size = node.size.unwrap_or(0);
+        let mut out = Vec::with_capacity(88);
+        push_u64(&mut out, ino);
+        push_u64(&mut out, size);
+        push_u64(&mut out, size.div_ceil(512));
+        for _ in 0..3 {
+            push_u64(&mut out, node.mtime);
+        }
+        for _ in 0..3 {
+            push_u32(&mut out, 0);
+        }
+        push_u32(&mut out, mode);
+        push_u32(&mut out, nlink);
+        push_u32(&mut out, self.uid);
+        push_u32(&mut out, self.gid);
+        push_u32(&mut out, 0);
+        push_u32(&mut out, 4096);
+        push_u32(&mut out, 0);
+        out
+    }
+
+    fn blob(&self, object: &str) -> Result<Vec<u8>, i32> {
+        let output = git::run(&self.repo, &["cat-file", "blob", object]).map_err(|_| EIO)?;
+        if !output.status.success() {
+            return Err(EIO);
+        }
+        Ok(output.stdout)
+    }
+
+    // What restore would write for the file.
+    fn content(&self, ino: u64) -> Result<Vec<u8>, i32> {
+        let node = self.node(ino)?;
+        let stored = self.blob(&node.object)?;
+        if node.storage == Storage::Plain {
+            return Ok(stored);
+        }
+        let delta = node.delta.as_deref().map(|delta| self.blob(delta)).transpose()?;
+        storage::decode(&self.config, node.storage, &stored, delta.as_deref()).map_err(|e| {
+            log::warn(&format!("could not read {} from the history: {}", node.path, e), &[]);
+            EIO
+        })
+    }
+
+    // Encrypted and delta copies are decoded once to learn their size.
+    fn measure(&mut self, ino: u64) -> Result<(), i32> {
+        if self.node(ino)?.size.is_none() {
+            let size = self.content(ino)?.len() as u64;
+            self.nodes[ino as usize - 1].size = Some(size);
+        }
+        Ok(())
+    }
+
+    // How the entry a stored path belongs to is stored; paths outside every
+    // entry are plain.
+    fn storage_of(&self, path: &str) -> Storage {
+        self.entries
+            .iter()
+            .find_map(|entry| {
+                let repo_path = entry.repo_path().to_string_lossy().into_owned();
+                let rest = path.strip_prefix(repo_path.as_str())?;
+                let stored_as = storage::storage(entry);
+                let suffixes = [storage::suffix(&self.config, stored_as), storage::DELTA_SUFFIX.to_string()];
+                (rest.is_empty() || rest.starts_with('/') || suffixes.iter().any(|suffix| rest == suffix))
+                    .then_some(stored_as)
+            })
+            .unwrap_or(Storage::Plain)
+    }
+
+    // `YYYY-MM-DD` is the last commit of that day, falling back to the latest
+    // commit before it; anything else is handed to git as a revision.
+    fn resolve_snapshot(&self, name: &str) -> Option<(String, u64)> {
+        let commit = if is_date(name) {
+            let before = format!("--before={} 23:59:59", name);
+            git::git(&self.repo, &["rev-list", "-1", &before, "HEAD"]).ok()?
+        } else {
+            git::git(&self.repo, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", name)]).ok()?
+        };
+        if commit.is_empty() {
+            return None;
+        }
+        let info = git::git(&self.repo, &["show", "-s", "--format=%T %ct", &commit]).ok()?;
+        let (tree, time) = info.split_once(' ')?;
+        Some((tree.to_string(), time.parse().ok()?))
+    }
+
+    fn list_snapshots(&self) -> Vec<(String, String, u64)> {
+        let Ok(log) = git::git(&self.repo, &["log", "--format=%T %ct %cs", "HEAD"]) else {
+            return Vec::new();
+        };
+        let mut snapshots: Vec<(String, String, u64)> = Vec::new();
+        for line in log.lines() {
+            let mut fields = line.split(' ');
+            let (Some(tree), Some(time), Some(day)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+            let time = time.parse().unwrap_or(0);
+            if snapshots.is_empty() {
+                snapshots.push(("HEAD".to_string(), tree.to_string(), time));
+            }
+            if !snapshots.iter().any(|(name, _, _)| name == day) {
+                snapshots.push((day.to_string(), tree.to_string(), time));
+            }
+        }
+        snapshots
+    }
+
+    fn list_tree(&self, path: &str, tree: &str, mtime: u64) -> Vec<(String, Node)> {
+        let Ok(output) = git::run(&self.repo, &["ls-tree", "-z", "-l", tree]) else {
+            return Vec::new();
+        };
+        let mut records = Vec::new();
+        for record in output.stdout.split(|b| *b == 0).filter(|r| !r.is_empty()) {
+            let record = String::from_utf8_lossy(record);
+            let Some((meta, name)) = record.split_once('\t') else {
+                continue;
+            };
+            let fields: Vec<&str> = meta.split_whitespace().collect();
+            let [mode, _, object, size] = fields[..] else {
+                continue;
+            };
+            records.push((mode.to_string(), object.to_string(), size.to_string(), name.to_string()));
+        }
+        let encrypted = storage::suffix(&self.config, Storage::Encrypted);
+        let mut children = Vec::new();
+        for (mode, object, size, name) in &records {
+            let kind = match mode.as_str() {
+                "040000" | "160000" => Kind::Dir,
+                "120000" => Kind::Symlink,
+                "100755" => Kind::File { executable: true },
+                _ => Kind::File { executable: false },
+            };
+            let stored_path = if path.is_empty() { name.clone() } else { format!("{}/{}", path, name) };
+            let mut node = Node {
+                kind,
+                path: stored_path.clone(),
+                // Submodules have no tree in this repository and show up empty.
+                object: if mode == "160000" { String::new() } else { object.clone() },
+                storage: Storage::Plain,
+                delta: None,
+                size: Some(size.parse().unwrap_or(0)),
+                mtime,
+                ttl: SNAPSHOT_TTL,
+                children: None,
+            };
+            let mut name = name.clone();
+            if matches!(kind, Kind::File { .. }) {
+                match self.storage_of(&stored_path) {
+                    Storage::Encrypted if name.ends_with(&encrypted) => {
+                        name.truncate(name.len() - encrypted.len());
+                        node.storage = Storage::Encrypted;
+                        node.size = None;
+                    }
+                    // The delta is read along with its base.
+                    Storage::Delta if name.ends_with(storage::DELTA_SUFFIX) => continue,
+                    Storage::Delta if name.ends_with(storage::BASE_SUFFIX) => {
+                        name.truncate(name.len() - storage::BASE_SUFFIX.len());
+                        let delta_name = format!("{}{}", name, storage::DELTA_SUFFIX);
+                        node.storage = Storage::Delta;
+                        let delta = records.iter().find(|record| record.3 == delta_name);
+                        node.delta = delta.map(|record| record.1.clone());
+                        node.size = None;
+                    }
+                    _ => {}
+                }
+            }
+            children.push((name, node));
+        }
+        children
+    }
+}
+
+fn snapshot_node(tree: String, mtime: u64) -> Node {
+    Node {
+        kind: Kind::Dir,
+        path: String::new(),
+        object: tree,
+        storage: Storage::Plain,
+        delta: None,
+        size: Some(0),
+        mtime,
+        ttl: ROOT_TTL,
+        children: None,
+    }
+}
+
+fn is_date(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() == 10
+        && bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && bytes.iter().enumerate().all(|(i, b)| i == 4 || i == 7 || b.is_ascii_digit())
+}
+
+fn init_reply(body: &[u8]) -> Vec<u8> {
+    let minor = read_u32(body, 4).min(31);
+    let max_readahead = read_u32(body, 8);
+    let mut out = Vec::with_capacity(64);
+    push_u32(&mut out, 7);
+    push_u32(&mut out, minor);
+    push_u32(&mut out, max_readahead);
+    push_u32(&mut out, 0);
+    out.extend_from_slice(&16u16.to_ne_bytes());
+    out.extend_from_slice(&12u16.to_ne_bytes());
+    push_u32(&mut out, MAX_WRITE as u32);
+    push_u32(&mut out, 1);
+    out.resize(64, 0);
+    if minor < 23 {
+        out.truncate(24);
+    }
+    out
+}
+
+fn open_out(fh: u64, flags: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(16);
+    push_u64(&mut out, fh);
+    push_u32(&mut out, flags);
+    push_u32(&mut out, 0);
+    out
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    buffer
+        .get(offset..offset + 4)
+        .map(|b| u32::from_ne_bytes(b.try_into().expect("4 bytes")))
+        .unwrap_or(0)
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    buffer
+        .get(offset..offset + 8)
+        .map(|b| u64::from_ne_bytes(b.try_into().expect("8 bytes")))
+        .unwrap_or(0)
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::delta;
+    use std::path::PathBuf;
+
+    // A backup repo with one commit holding `files`, and a config that stores
+    // `~/big` as a delta.
+    fn history(name: &str, files: &[(&str, &[u8])]) -> HistoryFs {
+        let repo = std::env::temp_dir().join(format!("syd-mount-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(&repo).unwrap();
+        for (path, content) in files {
+            fs::write(repo.join(path), content).unwrap();
+        }
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=syd", "-c", "user.email=syd@localhost", "-c", "commit.gpgsign=false"])
+                .args(args)
+                .current_dir(&repo)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        git(&["add", "-A"]);
+        git(&["commit", "-q", "-m", "Backup"]);
+        let text = format!("[backup]\nfolder = {:?}\n\n[[files]]\npath = \"~/big\"\ndelta = true\n", repo);
+        let (settings, entries, repos) = config::parse(&text).unwrap();
+        let config = Config {
+            path: PathBuf::from("syd.toml"),
+            settings,
+            entries,
+            repos,
+        };
+        HistoryFs::new(&config).unwrap()
+    }
+
+    fn lookup(fs: &mut HistoryFs, parent: u64, name: &str) -> Result<Vec<u8>, i32> {
+        fs.handle(FUSE_LOOKUP, parent, format!("{}\0", name).as_bytes())
+    }
+
+    // The size in an entry_out reply: attr follows 40 bytes of ids and ttls.
+    fn entry_size(entry: &[u8]) -> u64 {
+        read_u64(entry, 48)
+    }
+
+    fn read(fs: &mut HistoryFs, ino: u64, offset: u64, size: u32) -> Vec<u8> {
+        let opened = fs.handle(FUSE_OPEN, ino, &0u32.to_ne_bytes()).unwrap();
+        let fh = read_u64(&opened, 0);
+        let mut body = Vec::new();
+        push_u64(&mut body, fh);
+        push_u64(&mut body, offset);
+        push_u32(&mut body, size);
+        let data = fs.handle(FUSE_READ, ino, &body).unwrap();
+        fs.handle(FUSE_RELEASE, ino, &fh.to_ne_bytes()).unwrap();
+        data
+    }
+
+    // (ino, offset, type, name) of each record of a readdir reply.
+    fn dirents(mut reply: &[u8]) -> Vec<(u64, u64, u32, String)> {
+        let mut entries = Vec::new();
+        while !reply.is_empty() {
+            let length = read_u32(reply, 16) as usize;
+            let name = String::from_utf8_lossy(&reply[24..24 + length]).into_owned();
+            entries.push((read_u64(reply, 0), read_u64(reply, 8), read_u32(reply, 20), name));
+            reply = &reply[(24 + length + 7) & !7..];
+        }
+        entries
+    }
+
+    fn readdir(fs: &mut HistoryFs, ino: u64, offset: u64, size: u32) -> Vec<(u64, u64, u32, String)> {
+        let mut body = Vec::new();
+        push_u64(&mut body, 0);
+        push_u64(&mut body, offset);
+        push_u32(&mut body, size);
+        dirents(&fs.handle(FUSE_READDIR, ino, &body).unwrap())
+    }
+
+    #[test]
+    fn init_reply_matches_the_kernel_minor_version() {
+        let mut body = Vec::new();
+        push_u32(&mut body, 7);
+        push_u32(&mut body, 38);
+        push_u32(&mut body, 65536);
+        let reply = init_reply(&body);
+        assert_eq!(reply.len(), 64);
+        assert_eq!((read_u32(&reply, 0), read_u32(&reply, 4), read_u32(&reply, 8)), (7, 31, 65536));
+        assert_eq!(read_u32(&reply, 20), MAX_WRITE as u32);
+
+        let mut old = Vec::new();
+        push_u32(&mut old, 7);
+        push_u32(&mut old, 22);
+        assert_eq!(init_reply(&old).len(), 24);
+    }
+
+    #[test]
+    fn reads_files_of_a_revision() {
+        let mut fs = history("read", &[("notes.txt", b"hello world")]);
+        let head = read_u64(&lookup(&mut fs, ROOT, "HEAD").unwrap(), 0);
+        let entry = lookup(&mut fs, head, "notes.txt").unwrap();
+        let notes = read_u64(&entry, 0);
+        assert_eq!(entry_size(&entry), 11);
+        assert_eq!(read(&mut fs, notes, 6, 100), b"world");
+        assert_eq!(read(&mut fs, notes, 20, 100), b"");
+        assert_eq!(lookup(&mut fs, head, "missing"), Err(ENOENT));
+        assert_eq!(lookup(&mut fs, ROOT, "no-such-revision"), Err(ENOENT));
+    }
+
+    #[test]
+    fn lists_snapshots_by_day() {
+        let mut fs = history("days", &[("notes.txt", b"hello")]);
+        let names: Vec<String> = readdir(&mut fs, ROOT, 0, 4096).into_iter().map(|entry| entry.3).collect();
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[..3], [".", "..", "HEAD"]);
+        assert!(is_date(&names[3]));
+    }
+
+    #[test]
+    fn rebuilds_delta_copies() {
+        let base: Vec<u8> = (0..4000u32).flat_map(|n| format!("{}\n", n).into_bytes()).collect();
+        let mut target = base.clone();
+        target.splice(100..104, b"changed".iter().copied());
+        let patch = delta::diff(&base, &target);
+        let mut fs = history("delta", &[("big.syd-base", &base), ("big.syd-delta", &patch)]);
+        let head = read_u64(&lookup(&mut fs, ROOT, "HEAD").unwrap(), 0);
+        let names: Vec<String> = readdir(&mut fs, head, 0, 4096).into_iter().map(|entry| entry.3).collect();
+        assert_eq!(names, [".", "..", "big"]);
+        let entry = lookup(&mut fs, head, "big").unwrap();
+        assert_eq!(entry_size(&entry), target.len() as u64);
+        assert_eq!(read(&mut fs, read_u64(&entry, 0), 0, 1 << 20), target);
+    }
+
+    #[test]
+    fn readdir_records_are_aligned_and_resume_at_the_offset() {
+        let mut fs = history("readdir", &[("a", b"1"), ("longer-name", b"2"), ("z.sh", b"3")]);
+        let head = read_u64(&lookup(&mut fs, ROOT, "HEAD").unwrap(), 0);
+        let all = readdir(&mut fs, head, 0, 4096);
+        let names: Vec<&str> = all.iter().map(|entry| entry.3.as_str()).collect();
+        assert_eq!(names, [".", "..", "a", "longer-name", "z.sh"]);
+        assert!(all.iter().skip(2).all(|entry| entry.2 == 8));
+        // Each record names the offset to continue after it.
+        let rest = readdir(&mut fs, head, all[2].1, 4096);
+        assert_eq!(rest.iter().map(|entry| entry.3.as_str()).collect::<Vec<_>>(), ["longer-name", "z.sh"]);
+        // Records that do not fit are left for the next call.
+        assert_eq!(readdir(&mut fs, head, 0, 70).len(), 2);
+    }
+
+    #[test]
+    fn refuses_writes() {
+        let mut fs = history("writes", &[("notes.txt", b"hello")]);
+        let head = read_u64(&lookup(&mut fs, ROOT, "HEAD").unwrap(), 0);
+        let notes = read_u64(&lookup(&mut fs, head, "notes.txt").unwrap(), 0);
+        assert_eq!(fs.handle(FUSE_OPEN, notes, &1u32.to_ne_bytes()), Err(EROFS));
+        for opcode in WRITE_OPCODES {
+            assert_eq!(fs.handle(opcode, notes, &[]), Err(EROFS));
+        }
+        assert_eq!(fs.handle(99, notes, &[]), Err(ENOSYS));
+        assert_eq!(fs.handle(FUSE_OPENDIR, notes, &[]), Err(ENOTDIR));
+    }
+
+    #[test]
+    fn dates_are_recognized() {
+        assert!(is_date("2024-05-01"));
+        assert!(!is_date("2024-5-01"));
+        assert!(!is_date("HEAD~10000"));
+        assert!(!is_date("2024-05-0a"));
+    }
+}
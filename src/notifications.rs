@@ -0,0 +1,116 @@
+use crate::backup::BackupReport;
+use crate::config::Config;
+use crate::git;
+use crate::http;
+use crate::json;
+use crate::time;
+use std::io;
+
+// Pings a healthchecks.io-style dead-man switch: the configured URL on
+// success, the same URL with `/fail` appended when the run failed.
+pub fn ping_healthcheck(config: &Config, result: &io::Result<BackupReport>) -> io::Result<()> {
+    let Some(url) = config.settings.str("notifications.healthcheck_url") else {
+        return Ok(());
+    };
+    let url = url.trim_end_matches('/');
+    let (url, body) = match result {
+        Ok(report) => (
+            url.to_string(),
+            format!(
+                "syd backup succeeded: {} file(s) changed, {} bytes copied",
+                report.files_changed, report.bytes_copied
+            ),
+        ),
+        Err(e) => (format!("{}/fail", url), format!("syd backup failed: {}", e)),
+    };
+    match config.settings.str("notifications.healthcheck_method").unwrap_or("get") {
+        "get" | "GET" => http::request("GET", &url, &[], None),
+        "post" | "POST" => http::request("POST", &url, &["Content-Type: text/plain"], Some(&body)),
+        other => Err(io::Error::other(format!("unsupported healthcheck_method {:?}", other))),
+    }
+    .map(|_| ())
+}
+
+const DEFAULT_TEMPLATE: &str = "syd on {host}: backup {event} at {time}: {error}";
+
+// Sends failure and conflict notifications to the configured webhook and
+// mail providers. Each provider is tried even if an earlier one failed.
+pub fn notify_failure(config: &Config, error: &io::Error) -> io::Result<()> {
+    let (event, files) = match git::conflict(error) {
+        Some(conflict) => ("conflict", conflict.files.clone()),
+        None => ("failure", Vec::new()),
+    };
+    let host = git::hostname();
+    let time = time::format_rfc3339(time::unix_now());
+    let error = error.to_string();
+    let template = config.settings.str("notifications.template").unwrap_or(DEFAULT_TEMPLATE);
+    let message = render(
+        template,
+        &[
+            ("host", &host),
+            ("event", event),
+            ("error", &error),
+            ("files", &files.join(", ")),
+            ("time", &time),
+        ],
+    );
+
+    let mut failures = Vec::new();
+    if let Some(url) = config.settings.str("notifications.webhook_url") {
+        let format = config.settings.str("notifications.webhook_format").unwrap_or("json");
+        let payload = match format {
+            "slack" => Ok(json::object(&[("text", json::string(&message))])),
+            "discord" => Ok(json::object(&[("content", json::string(&message))])),
+            "ntfy" => Ok(message.clone()),
+            "json" => Ok(json::object(&[
+                ("event", json::string(event)),
+                ("host", json::string(&host)),
+                ("time", json::string(&time)),
+                ("error", json::string(&error)),
+                ("files", json::array(&files.iter().map(|f| json::string(f)).collect::<Vec<_>>())),
+                ("message", json::string(&message)),
+            ])),
+            other => Err(io::Error::other(format!("unsupported webhook_format {:?}", other))),
+        };
+        let title = format!("Title: syd backup {} on {}", event, host);
+        let headers: &[&str] = match format {
+            "ntfy" => &[&title, "Tags: warning"],
+            _ => &["Content-Type: application/json"],
+        };
+        if let Err(e) = payload.and_then(|payload| http::request("POST", url, headers, Some(&payload))) {
+            failures.push(e);
+        }
+    }
+    if let Some(url) = config.settings.str("notifications.smtp_url") {
+        let to = config.settings.list("notifications.email_to");
+        let from = config
+            .settings
+            .str("notifications.email_from")
+            .map(String::from)
+            .unwrap_or_else(|| format!("syd@{}", host));
+        let mail = format!(
+            "From: {}\r\nTo: {}\r\nSubject: syd backup {} on {}\r\n\r\n{}\r\n",
+            from,
+            to.join(", "),
+            event,
+            host,
+            message
+        );
+        let user = config.settings.str("notifications.smtp_user");
+        if let Err(e) = http::send_mail(url, user, &from, &to, &mail) {
+            failures.push(e);
+        }
+    }
+    match failures.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = template.to_string();
+    for (key, value) in values {
+        out = out.replace(&format!("{{{}}}", key), value);
+    }
+    out
+}
@@ -0,0 +1,245 @@
+// The operations journal: one line per backup, restore and undo in the state
+// directory, whatever started it (a terminal, cron, the daemon or the web
+// page), with its outcome and the files it touched. Git history only shows
+// the runs that committed something; `syd ops` shows all of them. Runs that
+// changed nothing are folded into the previous line when that was the same
+// kind of empty run, so an hourly daemon idling for a week takes one line,
+// and only the newest MAX_RECORDS lines are kept.
+use crate::files;
+use crate::git;
+use crate::log;
+use crate::output;
+use crate::state;
+use crate::time;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const OPS_FILE: &str = "ops";
+const MAX_RECORDS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Trigger {
+    #[default]
+    Terminal,
+    // Started without a terminal, from cron or a script.
+    Unattended,
+    Daemon,
+    Web,
+}
+
+impl Trigger {
+    // Terminal or Unattended, for commands run by hand or from cron.
+    pub fn current() -> Trigger {
+        match output::interactive() {
+            true => Trigger::Terminal,
+            false => Trigger::Unattended,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Trigger::Terminal => "terminal",
+            Trigger::Unattended => "unattended",
+            Trigger::Daemon => "daemon",
+            Trigger::Web => "web",
+        }
+    }
+
+    fn parse(label: &str) -> Option<Trigger> {
+        [Trigger::Terminal, Trigger::Unattended, Trigger::Daemon, Trigger::Web]
+            .into_iter()
+            .find(|trigger| trigger.label() == label)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub started: u64,
+    // When the last of the folded empty runs started.
+    pub last: u64,
+    pub runs: u64,
+    pub op: String,
+    pub trigger: Trigger,
+    pub host: String,
+    // None when the run succeeded.
+    pub error: Option<String>,
+    pub files: Vec<PathBuf>,
+}
+
+impl Record {
+    fn format(&self) -> String {
+        let mut fields = vec![
+            self.started.to_string(),
+            self.last.to_string(),
+            self.runs.to_string(),
+            self.op.clone(),
+            self.trigger.label().to_string(),
+            self.host.clone(),
+            match &self.error {
+                Some(error) => format!("failed {}", error.replace(['\t', '\n', '\r'], " ")),
+                None => "ok".to_string(),
+            },
+        ];
+        fields.extend(self.files.iter().map(|path| escape(&path.to_string_lossy())));
+        fields.join("\t")
+    }
+
+    fn parse(line: &str) -> Option<Record> {
+        let mut fields = line.split('\t');
+        let started = fields.next()?.parse().ok()?;
+        let last = fields.next()?.parse().ok()?;
+        let runs = fields.next()?.parse().ok()?;
+        let op = fields.next()?.to_string();
+        let trigger = Trigger::parse(fields.next()?)?;
+        let host = fields.next()?.to_string();
+        let error = match fields.next()? {
+            "ok" => None,
+            outcome => Some(outcome.strip_prefix("failed ").unwrap_or(outcome).to_string()),
+        };
+        Some(Record {
+            started,
+            last,
+            runs,
+            op,
+            trigger,
+            host,
+            error,
+            files: fields.map(|field| PathBuf::from(unescape(field))).collect(),
+        })
+    }
+
+    fn empty(&self) -> bool {
+        self.error.is_none() && self.files.is_empty()
+    }
+}
+
+// Tabs and line breaks in paths would split the record, so they are written
+// as `\t`, `\n` and `\r`, with backslashes doubled.
+fn escape(path: &str) -> String {
+    path.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+// Best effort like the log: a journal that cannot be written never fails the
+// operation itself.
+pub fn record(op: &str, trigger: Trigger, outcome: Result<&[PathBuf], &io::Error>) {
+    let now = time::unix_now();
+    let record = Record {
+        started: now,
+        last: now,
+        runs: 1,
+        op: op.to_string(),
+        trigger,
+        host: git::hostname(),
+        error: outcome.as_ref().err().map(|e| e.to_string()),
+        files: outcome.map(<[PathBuf]>::to_vec).unwrap_or_default(),
+    };
+    if let Err(e) = append(record) {
+        log::warn(&format!("failed to write the operations journal: {}", e), &[]);
+    }
+}
+
+fn append(record: Record) -> io::Result<()> {
+    let mut records = load()?;
+    match records.last_mut() {
+        Some(last) if record.empty() && last.empty() && last.op == record.op && last.trigger == record.trigger => {
+            last.last = record.started;
+            last.runs += 1;
+        }
+        _ => records.push(record),
+    }
+    let skip = records.len().saturating_sub(MAX_RECORDS);
+    let text: String = records[skip..].iter().map(|record| format!("{}\n", record.format())).collect();
+    files::replace(&state::state_dir()?.join(OPS_FILE), text.as_bytes())
+}
+
+// Oldest first. Lines that do not parse are skipped.
+pub fn load() -> io::Result<Vec<Record>> {
+    match fs::read_to_string(state::state_dir()?.join(OPS_FILE)) {
+        Ok(text) => Ok(text.lines().filter_map(Record::parse).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// Records with a run at or after `since`, a unix time.
+pub fn since(since: u64) -> io::Result<Vec<Record>> {
+    Ok(load()?.into_iter().filter(|record| record.last >= since).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(files: &[&str], error: Option<&str>) -> Record {
+        Record {
+            started: 1700000000,
+            last: 1700003600,
+            runs: 2,
+            op: "backup".to_string(),
+            trigger: Trigger::Daemon,
+            host: "laptop".to_string(),
+            error: error.map(String::from),
+            files: files.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let original = record(&["/home/me/.zshrc", "/home/me/notes dir/todo.txt"], None);
+        let line = original.format();
+        let header = "1700000000\t1700003600\t2\tbackup\tdaemon\tlaptop\tok";
+        assert_eq!(line, format!("{}\t/home/me/.zshrc\t/home/me/notes dir/todo.txt", header));
+        let parsed = Record::parse(&line).unwrap();
+        assert_eq!((parsed.started, parsed.last, parsed.runs), (1700000000, 1700003600, 2));
+        assert_eq!((parsed.op.as_str(), parsed.trigger, parsed.host.as_str()), ("backup", Trigger::Daemon, "laptop"));
+        assert_eq!(parsed.error, None);
+        assert_eq!(parsed.files, original.files);
+    }
+
+    #[test]
+    fn tabs_and_line_breaks_in_paths_are_escaped() {
+        let files = ["/tmp/tab\there", "/tmp/new\nline", "/tmp/back\\slash\\t", "/tmp/cr\r"];
+        let line = record(&files, None).format();
+        assert!(!line.contains(['\n', '\r']));
+        assert_eq!(line.split('\t').count(), 7 + files.len());
+        let parsed = Record::parse(&line).unwrap();
+        assert_eq!(parsed.files, files.iter().map(PathBuf::from).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn errors_are_kept_on_one_field() {
+        let line = record(&[], Some("push failed:\tremote\nrejected")).format();
+        assert!(line.ends_with("\tfailed push failed: remote rejected"));
+        let parsed = Record::parse(&line).unwrap();
+        assert_eq!(parsed.error.as_deref(), Some("push failed: remote rejected"));
+        assert!(parsed.files.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(Record::parse("").is_none());
+        assert!(Record::parse("1\t2\t3\tbackup\tcron\thost\tok").is_none());
+        assert!(Record::parse("x\t2\t3\tbackup\tdaemon\thost\tok").is_none());
+        assert!(Record::parse("1\t2\t3\tbackup\tdaemon\thost").is_none());
+    }
+}
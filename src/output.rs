@@ -0,0 +1,104 @@
+// How syd talks to whoever runs it. `--non-interactive` guarantees that
+// nothing prompts, even on a terminal, and switches progress and warnings on
+// stderr to JSON lines for provisioning tools; `--assume-yes` answers every
+// confirmation with yes. syd never prints color outside the picker, which
+// only runs interactively. `--a11y` is for screen readers: the picker becomes
+// a numbered list read line by line, and tables become one labeled line per
+// row instead of padded columns.
+use crate::events;
+use crate::json;
+use crate::time;
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+
+struct Mode {
+    non_interactive: bool,
+    assume_yes: bool,
+    a11y: bool,
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+pub fn init(non_interactive: bool, assume_yes: bool, a11y: bool) {
+    let _ = MODE.set(Mode {
+        non_interactive,
+        assume_yes,
+        a11y,
+    });
+}
+
+fn mode() -> &'static Mode {
+    MODE.get_or_init(|| Mode {
+        non_interactive: false,
+        assume_yes: false,
+        a11y: false,
+    })
+}
+
+// Whether syd may prompt: a terminal on stdin and no `--non-interactive`.
+pub fn interactive() -> bool {
+    !mode().non_interactive && io::stdin().is_terminal()
+}
+
+pub fn machine_readable() -> bool {
+    mode().non_interactive
+}
+
+pub fn accessible() -> bool {
+    mode().a11y
+}
+
+// A table row as given, or with `--a11y` its values as "label value" pairs
+// joined by commas. Empty values are left out.
+pub fn row(table: String, labeled: &[(&str, &str)]) -> String {
+    if !accessible() {
+        return table;
+    }
+    labeled
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(label, value)| match label.is_empty() {
+            true => value.to_string(),
+            false => format!("{} {}", label, value),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Asks a yes/no question. With `--assume-yes` the answer is yes, without a
+// way to ask it is no.
+pub fn confirm(question: &str) -> io::Result<bool> {
+    if mode().assume_yes {
+        return Ok(true);
+    }
+    if !interactive() {
+        return Ok(false);
+    }
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+// One JSON line on stderr per step, only for `--non-interactive` runs.
+pub fn progress(event: &str, fields: &[(&str, String)]) {
+    if !machine_readable() {
+        return;
+    }
+    let mut line = vec![("event", json::string(event))];
+    line.extend(fields.iter().map(|(key, value)| (*key, value.clone())));
+    eprintln!("{}", json::object(&line));
+}
+
+// A `progress` step that automation may react to, also sent to the events
+// destination with the time it happened (see `events`).
+pub fn event(event: &str, fields: &[(&str, String)]) {
+    progress(event, fields);
+    let mut line = vec![
+        ("event", json::string(event)),
+        ("time", json::string(&time::format_rfc3339(time::unix_now()))),
+    ];
+    line.extend(fields.iter().map(|(key, value)| (*key, value.clone())));
+    events::send(&json::object(&line));
+}
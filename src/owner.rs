@@ -0,0 +1,145 @@
+// Entries with `owner = "root"` or another user, for service configs that
+// live beside the dotfiles (systemd overrides, nginx snippets). Files syd
+// cannot read itself are read as the owner through `sudo -n`, and restores
+// always write them as the owner, so new files get the right owner too.
+// Running as root, syd writes them itself and hands them to the owner.
+// Without `owner`, or when syd already runs as that user, nothing changes.
+use crate::config::Entry;
+use crate::editor;
+use crate::exec;
+use crate::files;
+use std::fs;
+use std::io;
+use std::os::unix::{self, fs::PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn of(entry: &Entry) -> Option<&str> {
+    entry.options.str("owner")
+}
+
+fn current_user() -> &'static str {
+    static USER: OnceLock<String> = OnceLock::new();
+    USER.get_or_init(|| {
+        Command::new("id")
+            .arg("-un")
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_default()
+    })
+}
+
+// The owner syd has to switch to, None when it can act directly.
+pub fn other(owner: Option<&str>) -> Option<&str> {
+    owner.filter(|owner| *owner != current_user() && current_user() != "root")
+}
+
+fn id(owner: &str, flag: &str) -> io::Result<u32> {
+    let output = Command::new("id").args([flag, "--", owner]).output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::other(format!("unknown owner {:?}", owner)))
+}
+
+fn sudo(owner: &str, action: &str, path: &Path, args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut command = Command::new("sudo");
+    command.args(["-n", "-u", owner, "--"]).args(args);
+    exec::pipe(command, input).map_err(|e| {
+        io::Error::other(format!(
+            "{} {} as {} failed: {} (syd needs passwordless sudo for this, or run it as {})",
+            action,
+            path.display(),
+            owner,
+            e,
+            owner
+        ))
+    })
+}
+
+fn denied(result: &io::Result<impl Sized>) -> bool {
+    matches!(result, Err(e) if e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+pub fn read(owner: Option<&str>, path: &Path) -> io::Result<Vec<u8>> {
+    let direct = fs::read(path);
+    match other(owner) {
+        Some(owner) if denied(&direct) => {
+            sudo(owner, "reading", path, &["cat", "--", &path.to_string_lossy()], b"")
+        }
+        _ => direct,
+    }
+}
+
+// `files::walk`, listing directories syd cannot read with `find` as the owner.
+// Editor swap files and backups below a tracked directory are left out.
+pub fn walk(owner: Option<&str>, root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut found = walk_all(owner, root)?;
+    found.retain(|relative| relative.as_os_str().is_empty() || !editor::is_artifact(relative));
+    Ok(found)
+}
+
+fn walk_all(owner: Option<&str>, root: &Path) -> io::Result<Vec<PathBuf>> {
+    let direct = files::walk(root);
+    let Some(owner) = other(owner).filter(|_| denied(&direct)) else {
+        return direct;
+    };
+    let root_arg = root.to_string_lossy();
+    let args = ["find", &root_arg, "-name", ".git", "-prune", "-o", "!", "-type", "d", "-print0"];
+    let listing = sudo(owner, "listing", root, &args, b"")?;
+    let mut found: Vec<PathBuf> = listing
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let path = PathBuf::from(String::from_utf8_lossy(name).into_owned());
+            path.strip_prefix(root).ok().map(Path::to_path_buf)
+        })
+        .collect();
+    found.sort();
+    Ok(found)
+}
+
+// `files::write_file`, as the owner.
+pub fn write_file(owner: Option<&str>, destination: &Path, content: &[u8], like: &Path) -> io::Result<()> {
+    match owner.filter(|owner| *owner != current_user()) {
+        Some(owner) => write(owner, destination, content, fs::metadata(like)?.permissions().mode()),
+        None => files::write_file(destination, content, like),
+    }
+}
+
+// Writes `content` as `owner`, creating missing directories.
+pub fn write(owner: &str, destination: &Path, content: &[u8], mode: u32) -> io::Result<()> {
+    if current_user() == "root" {
+        if let Some(parent) = destination.parent() {
+            files::create_dirs(parent)?;
+        }
+        files::write(destination, content)?;
+        fs::set_permissions(destination, fs::Permissions::from_mode(mode & 0o7777))?;
+        return unix::fs::chown(destination, Some(id(owner, "-u")?), Some(id(owner, "-g")?));
+    }
+    let mode = format!("{:o}", mode & 0o7777);
+    let script = r#"mkdir -p -- "$(dirname -- "$1")" && cat > "$1" && chmod "$2" -- "$1""#;
+    let path = destination.to_string_lossy();
+    sudo(owner, "writing", destination, &["sh", "-c", script, "sh", &path, &mode], content).map(|_| ())
+}
+
+pub fn set_mtime(owner: Option<&str>, path: &Path, mtime: SystemTime) -> io::Result<()> {
+    let Some(owner) = other(owner) else {
+        return files::set_mtime(path, mtime);
+    };
+    let seconds = mtime.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+    let date = format!("@{}", seconds);
+    sudo(owner, "touching", path, &["touch", "-m", "-d", &date, "--", &path.to_string_lossy()], b"").map(|_| ())
+}
+
+pub fn remove(owner: Option<&str>, path: &Path) -> io::Result<()> {
+    match other(owner) {
+        Some(owner) => sudo(owner, "removing", path, &["rm", "-f", "--", &path.to_string_lossy()], b"").map(|_| ()),
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
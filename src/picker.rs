@@ -0,0 +1,301 @@
+// A small skim-style fuzzy picker drawn on /dev/tty. Type to filter, move
+// with the arrow keys or Ctrl-N/Ctrl-P, Tab marks entries for a multi-select,
+// Enter accepts the marked entries (or the highlighted one), Esc cancels.
+// With `--a11y` it is a numbered list on stdout instead, answered by line.
+use crate::output;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::process::{Command, Stdio};
+
+const MAX_ROWS: usize = 20;
+
+struct Terminal {
+    tty: File,
+    saved: String,
+}
+
+impl Terminal {
+    fn open() -> io::Result<Terminal> {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let saved = stty(&tty, &["-g"])?;
+        stty(&tty, &["raw", "-echo", "min", "0", "time", "1"])?;
+        let mut terminal = Terminal { tty, saved };
+        terminal.tty.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        Ok(terminal)
+    }
+
+    fn rows(&self) -> usize {
+        stty(&self.tty, &["size"])
+            .ok()
+            .and_then(|size| size.split_whitespace().next().and_then(|rows| rows.parse().ok()))
+            .filter(|rows| *rows > 0)
+            .unwrap_or(24)
+    }
+
+    // Reads one key, waiting until one arrives. Escape sequences for the
+    // arrow keys are returned whole.
+    fn key(&mut self) -> io::Result<Vec<u8>> {
+        let mut byte = [0u8; 1];
+        while self.tty.read(&mut byte)? == 0 {}
+        if byte[0] != 0x1b {
+            return Ok(byte.to_vec());
+        }
+        let mut sequence = vec![0x1b];
+        let mut rest = [0u8; 2];
+        let read = self.tty.read(&mut rest)?;
+        sequence.extend_from_slice(&rest[..read]);
+        if read == 1 && rest[0] == b'[' && self.tty.read(&mut rest[1..])? == 1 {
+            sequence.push(rest[1]);
+        }
+        Ok(sequence)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.tty.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = stty(&self.tty, &[self.saved.as_str()]);
+    }
+}
+
+fn stty(tty: &File, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(tty.try_clone()?)
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Returns the chosen items, or None when the picker was cancelled.
+pub fn pick(prompt: &str, items: &[String]) -> io::Result<Option<Vec<String>>> {
+    if items.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+    if output::accessible() {
+        return pick_lines(prompt, items);
+    }
+    let mut terminal = Terminal::open()?;
+    let mut query = String::new();
+    let mut selected = vec![false; items.len()];
+    let mut cursor = 0;
+    loop {
+        let matches = filter(&query, items);
+        cursor = cursor.min(matches.len().saturating_sub(1));
+        let rows = MAX_ROWS.min(terminal.rows().saturating_sub(2)).max(1);
+        draw(&mut terminal.tty, prompt, &query, items, &matches, &selected, cursor, rows)?;
+
+        match terminal.key()?.as_slice() {
+            [3] | [7] | [0x1b] => return Ok(None),
+            [13] | [10] => {
+                let mut chosen: Vec<String> = (0..items.len())
+                    .filter(|&i| selected[i])
+                    .map(|i| items[i].clone())
+                    .collect();
+                if chosen.is_empty() {
+                    if let Some(&index) = matches.get(cursor) {
+                        chosen.push(items[index].clone());
+                    }
+                }
+                return Ok(Some(chosen));
+            }
+            [9] => {
+                if let Some(&index) = matches.get(cursor) {
+                    selected[index] = !selected[index];
+                    cursor += 1;
+                }
+            }
+            [0x1b, b'[', b'Z'] => {
+                if let Some(&index) = matches.get(cursor) {
+                    selected[index] = !selected[index];
+                    cursor = cursor.saturating_sub(1);
+                }
+            }
+            [1] => {
+                let all = matches.iter().all(|&i| selected[i]);
+                for &index in &matches {
+                    selected[index] = !all;
+                }
+            }
+            [0x1b, b'[', b'A'] | [16] | [11] => cursor = cursor.saturating_sub(1),
+            [0x1b, b'[', b'B'] | [14] => cursor += 1,
+            [127] | [8] => {
+                query.pop();
+            }
+            [21] => query.clear(),
+            [c] if c.is_ascii_graphic() || *c == b' ' => query.push(*c as char),
+            _ => {}
+        }
+    }
+}
+
+// Lists the matching items by number and reads a line: numbers separated by
+// spaces choose, `all` chooses every listed item, other text narrows the list
+// like the query of the picker, and an empty line cancels.
+fn pick_lines(prompt: &str, items: &[String]) -> io::Result<Option<Vec<String>>> {
+    let mut matches: Vec<usize> = (0..items.len()).collect();
+    let mut stdin = io::stdin().lock();
+    loop {
+        println!("{}: {} item(s)", prompt, matches.len());
+        for (number, &index) in matches.iter().enumerate() {
+            println!("{}. {}", number + 1, items[index]);
+        }
+        print!("Type numbers to choose, all, text to filter, or nothing to cancel: ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let answer = line.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        if answer == "all" {
+            return Ok(Some(matches.iter().map(|&index| items[index].clone()).collect()));
+        }
+        let numbers: Option<Vec<usize>> = answer
+            .split([' ', ','])
+            .filter(|word| !word.is_empty())
+            .map(|word| word.parse().ok().filter(|number| (1..=matches.len()).contains(number)))
+            .collect();
+        match numbers {
+            Some(numbers) => return Ok(Some(numbers.iter().map(|number| items[matches[number - 1]].clone()).collect())),
+            None => {
+                let narrowed = filter(answer, items);
+                match narrowed.is_empty() {
+                    true => println!("Nothing matches {}", answer),
+                    false => matches = narrowed,
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw(
+    tty: &mut File,
+    prompt: &str,
+    query: &str,
+    items: &[String],
+    matches: &[usize],
+    selected: &[bool],
+    cursor: usize,
+    rows: usize,
+) -> io::Result<()> {
+    let first = cursor.saturating_sub(rows - 1);
+    let mut screen = String::from("\x1b[H\x1b[2J");
+    screen.push_str(&format!("{} > {}\r\n", prompt, query));
+    screen.push_str(&format!(
+        "  {}/{} (Tab to mark, Enter to accept, Esc to cancel)\r\n",
+        matches.len(),
+        items.len()
+    ));
+    for (row, &index) in matches.iter().enumerate().skip(first).take(rows) {
+        let pointer = if row == cursor { '>' } else { ' ' };
+        let mark = if selected[index] { '*' } else { ' ' };
+        if row == cursor {
+            screen.push_str(&format!("\x1b[7m{}{} {}\x1b[0m\r\n", pointer, mark, items[index]));
+        } else {
+            screen.push_str(&format!("{}{} {}\r\n", pointer, mark, items[index]));
+        }
+    }
+    tty.write_all(screen.as_bytes())?;
+    tty.flush()
+}
+
+// Indices of the matching items, best match first.
+fn filter(query: &str, items: &[String]) -> Vec<usize> {
+    let mut scored: Vec<(i64, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| score(query, item).map(|score| (score, index)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(items[a.1].len().cmp(&items[b.1].len())));
+    scored.into_iter().map(|(_, index)| index).collect()
+}
+
+// Subsequence match with bonuses for consecutive characters and for matches
+// at the start of a path component or word. Smart case: an uppercase letter
+// in the query makes the match case sensitive.
+fn score(query: &str, candidate: &str) -> Option<i64> {
+    let sensitive = query.chars().any(|c| c.is_uppercase());
+    let normalize = |c: char| if sensitive { c } else { c.to_ascii_lowercase() };
+    let mut wanted = query.chars().filter(|c| !c.is_whitespace()).map(normalize).peekable();
+    let mut score = 0i64;
+    let mut previous: Option<char> = None;
+    let mut last_match: Option<usize> = None;
+    for (position, c) in candidate.chars().enumerate() {
+        let Some(&next) = wanted.peek() else {
+            break;
+        };
+        if normalize(c) == next {
+            score += 1;
+            match last_match {
+                Some(last) if last + 1 == position => score += 5,
+                Some(last) => score -= ((position - last) as i64).min(5),
+                None => {}
+            }
+            if matches!(previous, None | Some('/') | Some('.') | Some('_') | Some('-')) {
+                score += 4;
+            }
+            last_match = Some(position);
+            wanted.next();
+        }
+        previous = Some(c);
+    }
+    if wanted.peek().is_some() {
+        return None;
+    }
+    Some(score - candidate.len() as i64 / 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn matches_subsequences_only() {
+        assert!(score("zrc", "~/.zshrc").is_some());
+        assert!(score("", "anything").is_some());
+        assert_eq!(score("rcz", "~/.zshrc"), None);
+        assert_eq!(score("zshrcc", "~/.zshrc"), None);
+    }
+
+    #[test]
+    fn spaces_in_the_query_are_ignored() {
+        assert_eq!(score("nv init", "~/.config/nvim/init.lua"), score("nvinit", "~/.config/nvim/init.lua"));
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(score("readme", "README.md").is_some());
+        assert!(score("README", "README.md").is_some());
+        assert_eq!(score("Readme", "README.md"), None);
+    }
+
+    #[test]
+    fn prefers_consecutive_and_component_start_matches() {
+        let consecutive = score("git", "~/.gitconfig").unwrap();
+        let scattered = score("git", "~/.config/gnome/initrc.txt").unwrap();
+        assert!(consecutive > scattered);
+        let at_start = score("init", "~/.config/nvim/init.lua").unwrap();
+        let inside = score("init", "~/.config/nvim/xinitx.lua").unwrap();
+        assert!(at_start > inside);
+        // Among equal matches the shorter candidate wins.
+        assert!(score("vim", "~/.vimrc").unwrap() > score("vim", "~/.vimrc.local.backup.old").unwrap());
+    }
+
+    #[test]
+    fn filter_sorts_best_match_first() {
+        let items = items(&["~/.config/gnome/initrc", "~/.gitconfig", "~/.bashrc", "~/.gitignore"]);
+        assert_eq!(filter("git", &items), [1, 3, 0]);
+        assert_eq!(filter("", &items).len(), 4);
+    }
+}
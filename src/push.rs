@@ -0,0 +1,293 @@
+// Pushing to the remote: optional upload bandwidth limits, chunked pushes with
+// retries, and time windows outside of which unattended runs only commit
+// locally. Commits that were not pushed stay ahead of the remote and go out
+// with the next push.
+use crate::config::Config;
+use crate::doctor;
+use crate::forge;
+use crate::git;
+use crate::json;
+use crate::log;
+use crate::output;
+use crate::time;
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Hidden subcommand used as a wrapper around ssh or git-receive-pack.
+pub const THROTTLE_COMMAND: &str = "__throttle";
+
+// Push windows from `push.windows`, as `HH:MM-HH:MM` ranges in local time. A
+// range may wrap around midnight, e.g. `22:00-07:00`.
+fn windows(config: &Config) -> io::Result<Vec<(u32, u32)>> {
+    config
+        .settings
+        .list("push.windows")
+        .iter()
+        .map(|window| {
+            window
+                .split_once('-')
+                .and_then(|(start, end)| Some((time::parse_clock(start)?, time::parse_clock(end)?)))
+                .ok_or_else(|| io::Error::other(format!("invalid push window {:?}, expected HH:MM-HH:MM", window)))
+        })
+        .collect()
+}
+
+fn contains(window: (u32, u32), minute: u32) -> bool {
+    let (start, end) = window;
+    if start <= end {
+        (start..end).contains(&minute)
+    } else {
+        minute >= start || minute < end
+    }
+}
+
+// How long until automatic pushes are allowed again; zero when they are
+// allowed right now or no windows are configured.
+pub fn until_window(config: &Config) -> io::Result<Duration> {
+    let windows = windows(config)?;
+    if windows.is_empty() {
+        return Ok(Duration::ZERO);
+    }
+    let now = time::local_minute_of_day()?;
+    if windows.iter().any(|window| contains(*window, now)) {
+        return Ok(Duration::ZERO);
+    }
+    let minutes = windows
+        .iter()
+        .map(|(start, _)| (start + 24 * 60 - now) % (24 * 60))
+        .min()
+        .unwrap_or(0);
+    Ok(Duration::from_secs(u64::from(minutes) * 60))
+}
+
+// Reads `push.bandwidth_limit`, bytes per second with an optional k/M/G suffix.
+fn bandwidth_limit(config: &Config) -> io::Result<Option<u64>> {
+    let value = match (config.settings.int("push.bandwidth_limit"), config.settings.str("push.bandwidth_limit")) {
+        (Some(bytes), _) => return Ok(u64::try_from(bytes).ok().filter(|b| *b > 0)),
+        (None, Some(value)) => value,
+        (None, None) => return Ok(None),
+    };
+    parse_rate(value)
+        .map(Some)
+        .ok_or_else(|| io::Error::other(format!("invalid push.bandwidth_limit {:?}", value)))
+}
+
+fn parse_rate(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let factor = match unit.trim().trim_end_matches("/s").trim_end_matches(['b', 'B']) {
+        "" => 1,
+        "k" | "K" => 1024,
+        "m" | "M" => 1024 * 1024,
+        "g" | "G" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok().map(|n| n * factor).filter(|rate| *rate > 0)
+}
+
+// With `push.chunk_commits` the pending commits go out a few at a time, so a
+// dropped connection only loses the chunk in flight: git moves
+// origin/<branch> after every successful chunk and the next attempt, or the
+// next run, continues from there. Failed chunks are retried `push.retries`
+// times with a growing pause.
+pub fn push(config: &Config, folder: &Path, branch: &str) -> io::Result<()> {
+    let chunk = config.settings.int("push.chunk_commits").unwrap_or(0);
+    let retries = config.settings.int("push.retries").unwrap_or(0).max(0) as u32;
+    let mut targets = Vec::new();
+    if chunk > 0 {
+        let pending = pending_commits(folder, branch)?;
+        targets.extend(pending.iter().skip(chunk as usize - 1).step_by(chunk as usize).cloned());
+        if let Some(last) = pending.last().filter(|last| targets.last() != Some(*last)) {
+            targets.push(last.clone());
+        }
+    }
+    if targets.is_empty() {
+        targets.push(branch.to_string());
+    }
+    doctor::check_before_push(config, folder, branch);
+    let to_topic = match config.settings.str("push.protected_branch").unwrap_or("topic") {
+        "topic" => true,
+        "fail" => false,
+        other => return Err(io::Error::other(format!("unknown push.protected_branch {:?}", other))),
+    };
+    for (index, target) in targets.iter().enumerate() {
+        let mut attempt = 0;
+        loop {
+            match push_once(config, folder, target, branch) {
+                Ok(()) => break,
+                Err(e) if protected(&e) && to_topic => {
+                    return push_topic(config, folder, branch, &e);
+                }
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    let wait = Duration::from_secs(5 << (attempt - 1).min(6));
+                    log::warn(
+                        &format!("push failed, retrying in {}s: {}", wait.as_secs(), e),
+                        &[
+                            ("attempt", attempt.to_string()),
+                            ("chunk", format!("{}/{}", index + 1, targets.len())),
+                        ],
+                    );
+                    thread::sleep(wait);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(())
+}
+
+// Commits on the first-parent line that the remote branch does not have yet,
+// oldest first.
+fn pending_commits(folder: &Path, branch: &str) -> io::Result<Vec<String>> {
+    let upstream = format!("origin/{}", branch);
+    let range = if git::succeeds(folder, &["rev-parse", "--verify", "--quiet", &upstream])? {
+        format!("{}..HEAD", upstream)
+    } else {
+        "HEAD".to_string()
+    };
+    let commits = git::git(folder, &["rev-list", "--reverse", "--first-parent", &range])?;
+    Ok(commits.lines().map(String::from).collect())
+}
+
+// What GitHub, GitLab, Gitea and Bitbucket answer to pushes to a protected
+// branch.
+fn protected(error: &io::Error) -> bool {
+    let message = error.to_string().to_lowercase();
+    message.contains("protected branch") || message.contains("gh006") || message.contains("protected ref")
+}
+
+// Backups the forge refuses on a protected branch go to a topic branch
+// instead, `push.topic_branch` or `syd/<host>`. It is force-pushed since the
+// local commits are rebased onto the protected branch on every run, and holds
+// them until they are merged there. With `push.pull_request` a pull request
+// from it is opened, once.
+fn push_topic(config: &Config, folder: &Path, branch: &str, rejection: &io::Error) -> io::Result<()> {
+    let topic = topic_branch(config);
+    log::warn(
+        &format!("{} is protected on the remote, pushing the backup to {} instead", branch, topic),
+        &[("rejection", json::string(&rejection.to_string()))],
+    );
+    push_once(config, folder, "+HEAD", &topic)?;
+    if !config.settings.bool("push.pull_request").unwrap_or(false) {
+        return Ok(());
+    }
+    let upstream = format!("origin/{}", branch);
+    let range = match git::succeeds(folder, &["rev-parse", "--verify", "--quiet", &upstream])? {
+        true => format!("{}..HEAD", upstream),
+        false => "HEAD".to_string(),
+    };
+    let commits = git::git(folder, &["log", "--format=- %s", &range])?;
+    let stat = git::git(folder, &["diff", "--stat", &range])?;
+    let title = format!("Backup from {}", git::hostname());
+    let body = format!("Backups syd could not push to `{}`:\n\n{}\n\n```\n{}\n```\n", branch, commits, stat);
+    let opened = forge::open_pull_request(config, &topic, branch, &title, &body)?;
+    if let Some(url) = opened.filter(|_| !output::machine_readable()) {
+        eprintln!("syd: opened {} to merge the backup into {}", url, branch);
+    }
+    Ok(())
+}
+
+pub fn topic_branch(config: &Config) -> String {
+    match config.settings.str("push.topic_branch") {
+        Some(topic) => topic.to_string(),
+        None => format!("syd/{}", git::hostname()),
+    }
+}
+
+fn push_once(config: &Config, folder: &Path, revision: &str, branch: &str) -> io::Result<()> {
+    let mut command = git::command(folder);
+    command.args(["push", "--quiet"]);
+    if let Some(rate) = bandwidth_limit(config)? {
+        limit(config, &mut command, rate)?;
+    }
+    let output = command
+        .args(["origin", &format!("{}:refs/heads/{}", revision, branch)])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git push origin {} failed: {}",
+            branch,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+// Git has no upload limit of its own, so the process git feeds the pack to
+// is wrapped in `syd __throttle`: ssh for ssh remotes, git-receive-pack for
+// local ones. HTTP remotes cannot be limited this way.
+fn limit(config: &Config, command: &mut Command, rate: u64) -> io::Result<()> {
+    let remote = config.remote().unwrap_or("");
+    let wrapper = format!("'{}' {} {}", env::current_exe()?.display(), THROTTLE_COMMAND, rate);
+    if remote.starts_with("http://") || remote.starts_with("https://") {
+        log::warn(
+            "push.bandwidth_limit is not supported for http remotes, pushing at full speed",
+            &[("remote", json::string(remote))],
+        );
+    } else if remote.starts_with("ssh://") || (remote.contains(':') && !remote.starts_with("file://")) {
+        let ssh = env::var("GIT_SSH_COMMAND").unwrap_or_else(|_| "ssh".to_string());
+        command.env("GIT_SSH_COMMAND", format!("{} {}", wrapper, ssh));
+    } else {
+        command.arg(format!("--receive-pack={} git-receive-pack", wrapper));
+    }
+    Ok(())
+}
+
+// `syd __throttle RATE COMMAND...` runs the command through sh and relays
+// stdin to it at no more than RATE bytes per second.
+pub fn throttle(args: &[String]) -> ! {
+    let (Some(rate), Some(_)) = (args.first().and_then(|rate| rate.parse::<u64>().ok()), args.get(1)) else {
+        eprintln!("Usage: syd {} RATE COMMAND...", THROTTLE_COMMAND);
+        process::exit(2);
+    };
+    let spawned = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", args[1]))
+        .arg("sh")
+        .args(&args[2..])
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("syd: could not run {}: {}", args[1], e);
+            process::exit(1);
+        }
+    };
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let relay = thread::spawn(move || -> io::Result<()> {
+        let chunk = (rate / 10).clamp(1, 64 * 1024) as usize;
+        let mut buffer = vec![0; chunk];
+        let mut input = io::stdin().lock();
+        let start = Instant::now();
+        let mut sent: u64 = 0;
+        loop {
+            let read = input.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(());
+            }
+            stdin.write_all(&buffer[..read])?;
+            stdin.flush()?;
+            sent += read as u64;
+            let due = Duration::from_secs_f64(sent as f64 / rate as f64);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+    });
+    let status = child.wait();
+    drop(relay);
+    match status {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("syd: {}", e);
+            process::exit(1);
+        }
+    }
+}
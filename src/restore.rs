@@ -0,0 +1,343 @@
+use crate::cache::{self, StatCache};
+use crate::config::{self, home_dir, repo_path_for, Config, Value};
+use crate::exec;
+use crate::files;
+use crate::filter;
+use crate::git;
+use crate::json;
+use crate::lock;
+use crate::log;
+use crate::manifest::Manifest;
+use crate::metadata::{self, Mtimes};
+use crate::notifications;
+use crate::ops::{self, Trigger};
+use crate::output;
+use crate::owner;
+use crate::storage;
+use crate::store;
+use crate::trial;
+use crate::undo::{self, Journal};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::SystemTime;
+
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub restored: usize,
+    pub unchanged: usize,
+    pub files: Vec<PathBuf>,
+    pub journal: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Overwrite,
+    Skip,
+}
+
+impl Action {
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Overwrite => "overwrite",
+            Action::Skip => "skip",
+        }
+    }
+}
+
+struct Planned {
+    content: Vec<u8>,
+    source: PathBuf,
+    target: PathBuf,
+    mtime: Option<SystemTime>,
+    fingerprint: String,
+    owner: Option<String>,
+}
+
+// The files to write, and those already up to date.
+#[derive(Default)]
+struct Plan {
+    write: Vec<Planned>,
+    unchanged: Vec<Planned>,
+}
+
+// What a restore would do, without writing anything: the path, the action
+// and the size of the restored content for every file.
+pub fn preview(config: &Config, only: &[PathBuf], root: Option<&Path>) -> io::Result<Vec<(PathBuf, Action, u64)>> {
+    let plan = plan(config, only, root)?;
+    let mut preview: Vec<_> = plan
+        .write
+        .into_iter()
+        .map(|item| {
+            let action = if item.target.exists() { Action::Overwrite } else { Action::Create };
+            (item.target, action, item.content.len() as u64)
+        })
+        .chain(plan.unchanged.into_iter().map(|item| (item.target, Action::Skip, item.content.len() as u64)))
+        .collect();
+    preview.sort_by(|a, b| (a.0.parent(), &a.0).cmp(&(b.0.parent(), &b.0)));
+    Ok(preview)
+}
+
+fn plan(config: &Config, only: &[PathBuf], root: Option<&Path>) -> io::Result<Plan> {
+    let mut plan = Plan::default();
+    for repo in config.repo_configs()? {
+        let mtimes = Mtimes::load(&repo.backup_folder())?;
+        let manifest = Manifest::load(&repo.backup_folder())?;
+        for entry in &repo.entries {
+            // The stat cache is keyed on the configured entry, as in backups.
+            let fingerprint = cache::fingerprint(&repo, entry);
+            let resolved = manifest.as_ref().map(|manifest| manifest.resolve(entry));
+            let entry = resolved.as_ref().unwrap_or(entry);
+            let preserve_mtime = metadata::preserve_mtime(&repo, entry);
+            let stored = storage::stored_files(&repo, entry, &repo.backup_folder())?;
+            if stored.is_empty() {
+                log::warn(
+                    &format!("skipping {}: not in the backup", entry.path.display()),
+                    &[("path", json::string(&entry.path.to_string_lossy()))],
+                );
+                continue;
+            }
+            for (target, source) in stored {
+                if !only.is_empty() && !only.iter().any(|path| target.starts_with(path)) {
+                    continue;
+                }
+                let mtime = preserve_mtime.then(|| mtimes.get(&repo_path_for(&target))).flatten();
+                let target = match root {
+                    None => target,
+                    Some(root) => match target.strip_prefix(home_dir()) {
+                        Ok(relative) => root.join(relative),
+                        Err(_) => {
+                            log::warn(
+                                &format!("skipping {}: not below the home directory", target.display()),
+                                &[("path", json::string(&target.to_string_lossy()))],
+                            );
+                            continue;
+                        }
+                    },
+                };
+                let content = filter::smudge(entry, storage::load(&repo, entry, &source)?, &target)?;
+                let unchanged = owner::read(owner::of(entry), &target).ok().as_deref() == Some(content.as_slice());
+                if !unchanged {
+                    filter::validate(entry, &content, &target)?;
+                }
+                let planned = Planned {
+                    content,
+                    source,
+                    target,
+                    mtime,
+                    fingerprint: fingerprint.clone(),
+                    owner: owner::of(entry).map(String::from),
+                };
+                match unchanged {
+                    true => plan.unchanged.push(planned),
+                    false => plan.write.push(planned),
+                }
+            }
+        }
+    }
+    Ok(plan)
+}
+
+// Restores every tracked file, or only those below one of `only` when given.
+// With `root` the files of the home directory are written below it instead,
+// owned by whoever owns `root`, and files outside of it are skipped. The
+// backup folder should be brought up to date with `update_backup_folder`
+// first.
+pub fn restore_dotfiles(config: &Config, only: &[PathBuf], root: Option<&Path>) -> io::Result<RestoreReport> {
+    let retention = config
+        .settings
+        .duration("restore.undo_retention")
+        .unwrap_or(undo::DEFAULT_RETENTION);
+    undo::prune(retention)?;
+
+    let plan = plan(config, only, root)?;
+    let mut report = RestoreReport {
+        unchanged: plan.unchanged.len(),
+        ..RestoreReport::default()
+    };
+
+    // A restore either applies completely or not at all: when one file fails
+    // the files already written in this run are rolled back.
+    let mut journal = Journal::begin()?;
+    let mut cache = StatCache::load()?;
+    for Planned { content, source, target, mtime, fingerprint, owner } in &plan.write {
+        let owner = owner.as_deref();
+        let written = journal.record(target, owner).and_then(|_| {
+            owner::write_file(owner, target, content, source)?;
+            if let Some(root) = root {
+                files::adopt_owner(root, target)?;
+            }
+            match mtime {
+                Some(mtime) => owner::set_mtime(owner, target, *mtime),
+                None => Ok(()),
+            }
+        });
+        if let Err(error) = written {
+            let outcome = match journal.rollback() {
+                Ok(count) => format!("rolled back {} file(s), nothing was changed", count),
+                Err(e) => format!("rollback failed: {}, run `syd undo` to retry", e),
+            };
+            return Err(io::Error::new(
+                error.kind(),
+                format!("restoring {} failed: {}; {}", target.display(), error, outcome),
+            ));
+        }
+        // Files restored elsewhere are not the ones the next backup reads.
+        if root.is_none() {
+            cache.record(target, fingerprint);
+        }
+        output::event("restored", &[("path", json::string(&target.to_string_lossy()))]);
+        report.restored += 1;
+        report.files.push(target.clone());
+    }
+    cache.save()?;
+    report.journal = journal.finish()?;
+    Ok(report)
+}
+
+// Pulls and restores the tracked files, or those below `only`, overwriting
+// local edits, as the daemon does in `daemon.mode = "restore"` and its web
+// page does on request. Failures are notified like those of unattended
+// backups.
+pub fn pull_and_restore(config: &Config, only: &[PathBuf], trigger: Trigger) -> io::Result<RestoreReport> {
+    log::init(config);
+    files::init(config)?;
+    let _lock = lock::write_lock()?;
+    let result = match trial::active()? {
+        Some(branch) => Err(io::Error::other(format!(
+            "not restoring while trying {}, run `syd try --end` first",
+            branch
+        ))),
+        None => update_backup_folder(config, false).and_then(|_| restore_dotfiles(config, only, None)),
+    };
+    ops::record("restore", trigger, result.as_ref().map(|report| report.files.as_slice()));
+    let report = match result {
+        Ok(report) => report,
+        Err(error) => {
+            if let Err(e) = notifications::notify_failure(config, &error) {
+                log::warn(&format!("failed to send notification: {}", e), &[]);
+            }
+            return Err(error);
+        }
+    };
+    log::info(
+        "restore finished",
+        &[
+            ("restored", report.restored.to_string()),
+            ("unchanged", report.unchanged.to_string()),
+            ("trigger", json::string(trigger.label())),
+        ],
+    );
+    Ok(report)
+}
+
+// Home directory paths of all files the backup has for the configured entries.
+pub fn tracked_files(config: &Config) -> io::Result<Vec<PathBuf>> {
+    let mut tracked = Vec::new();
+    for repo in config.repo_configs()? {
+        let manifest = Manifest::load(&repo.backup_folder())?;
+        for entry in &repo.entries {
+            let resolved = manifest.as_ref().map(|manifest| manifest.resolve(entry));
+            let entry = resolved.as_ref().unwrap_or(entry);
+            for (target, _) in storage::stored_files(&repo, entry, &repo.backup_folder())? {
+                tracked.push(target);
+            }
+        }
+    }
+    Ok(tracked)
+}
+
+#[derive(Debug, Default)]
+pub struct SelftestReport {
+    pub checked: usize,
+    // Live files that differ from what a restore from HEAD would write.
+    pub differ: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+}
+
+// Extracts each repo's HEAD into a temporary directory, restores every
+// tracked file from it into a sandbox there and compares the result with the
+// live files, without touching $HOME, the backup folders or the undo log.
+pub fn selftest(config: &Config) -> io::Result<SelftestReport> {
+    let sandbox = env::temp_dir().join(format!("syd-selftest-{}", process::id()));
+    let result = selftest_in(config, &sandbox);
+    if sandbox.exists() {
+        fs::remove_dir_all(&sandbox)?;
+    }
+    result
+}
+
+fn selftest_in(config: &Config, sandbox: &Path) -> io::Result<SelftestReport> {
+    let mut head = config.clone();
+    let checkout = |repo: &Config, name: &str| -> io::Result<String> {
+        let folder = &repo.backup_folder();
+        let dir = sandbox.join("repos").join(name);
+        fs::create_dir_all(&dir)?;
+        if !store::is_git(repo) {
+            // Other VCSs have no `git archive`; their checkout is what a
+            // restore would read anyway.
+            for relative in files::walk(folder)? {
+                files::copy_file(&files::join(folder, &relative), &files::join(&dir, &relative))?;
+            }
+        } else if git::succeeds(folder, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+            let output = git::run(folder, &["archive", "--format=tar", "HEAD"])?;
+            if !output.status.success() {
+                return Err(io::Error::other(format!(
+                    "git archive in {} failed: {}",
+                    folder.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+            let mut tar = Command::new("tar");
+            tar.arg("-x").arg("-C").arg(&dir);
+            exec::pipe(tar, &output.stdout)?;
+        }
+        Ok(dir.to_string_lossy().into_owned())
+    };
+    // The [[repos]] come last in `repo_configs`, in their order, after the
+    // default repo unless only [[repos]] have entries.
+    let repos = config.repo_configs()?;
+    let (default, named) = repos.split_at(repos.len() - config.repos.len());
+    if let Some(repo) = default.first() {
+        let folder = checkout(repo, config::DEFAULT_REPO)?;
+        head.settings.insert("backup.folder".to_string(), Value::Str(folder));
+    }
+    for (repo, table) in named.iter().zip(&mut head.repos) {
+        let name = table.str("name").unwrap_or_default().to_string();
+        let folder = checkout(repo, &name)?;
+        table.insert("folder".to_string(), Value::Str(folder));
+    }
+
+    let plan = plan(&head, &[], None)?;
+    let mut report = SelftestReport {
+        checked: plan.write.len() + plan.unchanged.len(),
+        ..SelftestReport::default()
+    };
+    for item in plan.write.iter().chain(&plan.unchanged) {
+        let restored = sandbox.join("files").join(item.target.strip_prefix("/").unwrap_or(&item.target));
+        files::write_file(&restored, &item.content, &item.source)?;
+        if fs::read(&restored)? != item.content {
+            return Err(io::Error::other(format!("{} did not read back as written", restored.display())));
+        }
+    }
+    for item in &plan.write {
+        match item.target.exists() {
+            true => report.differ.push(item.target.clone()),
+            false => report.missing.push(item.target.clone()),
+        }
+    }
+    Ok(report)
+}
+
+// Brings every repo's backup folder up to date with its remote, see
+// `Store::pull`.
+pub fn update_backup_folder(config: &Config, accept_rewrite: bool) -> io::Result<()> {
+    for repo in config.repo_configs()? {
+        store::open(&repo)?.pull(accept_rewrite)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,434 @@
+// `syd scrub`: removes what should never have been committed from the whole
+// history of the backup repos. Paths and glob patterns drop those files from
+// every commit; `--text` replaces a string, such as a leaked token, in every
+// version of every file. The history goes through `git fast-export` and back
+// through `git fast-import`, which rewrites the tags along with the branches,
+// then the old objects are pruned and every branch and tag is force-pushed.
+// Other machines see the rewrite and refuse to restore until told to with
+// `--accept-rewrite`. Rewritten commits lose their signatures. `--text` cannot
+// look inside encrypted copies, which are reported, and is refused while delta
+// copies remain, since every version is built from the bytes of the one before.
+use crate::config::{self, Config};
+use crate::crypto;
+use crate::exec;
+use crate::git;
+use crate::json;
+use crate::log;
+use crate::storage::{BASE_SUFFIX, DELTA_SUFFIX};
+use crate::store;
+use crate::trust;
+use std::collections::BTreeSet;
+use std::io;
+use std::path::PathBuf;
+
+const REPLACEMENT: &[u8] = b"***REMOVED***";
+
+#[derive(Default)]
+pub struct Scrub {
+    // Home paths or paths in the repo; directories take their contents along.
+    pub paths: Vec<String>,
+    pub texts: Vec<String>,
+}
+
+pub struct ScrubReport {
+    pub folder: PathBuf,
+    // The paths dropped from history.
+    pub paths: BTreeSet<String>,
+    // How many file versions had text replaced.
+    pub blobs: usize,
+    // The encrypted copies `--text` could not search.
+    pub encrypted: BTreeSet<String>,
+    pub pushed: bool,
+}
+
+impl ScrubReport {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.blobs == 0
+    }
+}
+
+// What `scrub` would remove, without changing anything.
+pub fn preview(config: &Config, scrub: &Scrub) -> io::Result<Vec<ScrubReport>> {
+    let mut reports = Vec::new();
+    for repo in git_repos(config)? {
+        let folder = repo.backup_folder();
+        if repo.remote().is_some() {
+            git::fetch(&folder)?;
+        }
+        let (_, report) = rewrite(&repo, scrub, &export(&repo)?)?;
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+pub fn scrub(config: &Config, scrub: &Scrub) -> io::Result<Vec<ScrubReport>> {
+    let mut reports = Vec::new();
+    for repo in git_repos(config)? {
+        let folder = repo.backup_folder();
+        let (stream, mut report) = rewrite(&repo, scrub, &export(&repo)?)?;
+        if report.is_empty() {
+            reports.push(report);
+            continue;
+        }
+        let mut import = git::command(&folder);
+        import.args(["fast-import", "--force", "--quiet"]);
+        exec::pipe(import, &stream)?;
+        git::git(&folder, &["reset", "--hard", "--quiet"])?;
+        git::git(&folder, &["reflog", "expire", "--expire=now", "--all"])?;
+        git::git(&folder, &["gc", "--prune=now", "--quiet"])?;
+        if repo.remote().is_some() {
+            push_everything(&repo)?;
+            trust::remember_remote_head(&repo, &folder)?;
+            report.pushed = true;
+        }
+        log::info(
+            "scrubbed history",
+            &[
+                ("folder", json::string(&folder.to_string_lossy())),
+                ("paths", report.paths.len().to_string()),
+                ("blobs", report.blobs.to_string()),
+            ],
+        );
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+fn git_repos(config: &Config) -> io::Result<Vec<Config>> {
+    let mut repos = config.repo_configs()?;
+    repos.retain(|repo| store::is_git(repo) && repo.backup_folder().join(".git").exists());
+    Ok(repos)
+}
+
+// Remote-tracking branches are exported too, so the branches other machines
+// back up to are cleaned as well.
+fn export(config: &Config) -> io::Result<Vec<u8>> {
+    let mut command = git::command(&config.backup_folder());
+    command.args(["fast-export", "--all", "--signed-tags=strip", "--tag-of-filtered-object=rewrite"]);
+    exec::pipe(command, b"")
+}
+
+fn push_everything(config: &Config) -> io::Result<()> {
+    let folder = config.backup_folder();
+    let branch = config.branch();
+    let mut refspecs = vec![format!("+refs/heads/{}:refs/heads/{}", branch, branch)];
+    for name in git::git(&folder, &["for-each-ref", "--format=%(refname:strip=3)", "refs/remotes/origin"])?.lines() {
+        if name != "HEAD" && name != branch {
+            refspecs.push(format!("+refs/remotes/origin/{}:refs/heads/{}", name, name));
+        }
+    }
+    refspecs.push("+refs/tags/*:refs/tags/*".to_string());
+    let mut args = vec!["push", "--quiet", "origin"];
+    args.extend(refspecs.iter().map(String::as_str));
+    git::git(&folder, &args).map(|_| ())
+}
+
+// The paths in the repo an argument stands for: the stored copy of a tracked
+// file or directory, including the encrypted and delta variants, or a glob.
+struct Matcher {
+    prefixes: Vec<String>,
+    suffixes: Vec<String>,
+    globs: Vec<String>,
+}
+
+impl Matcher {
+    fn new(config: &Config, paths: &[String]) -> Matcher {
+        let mut matcher = Matcher {
+            prefixes: Vec::new(),
+            suffixes: vec![format!(".{}", crypto::extension(config)), ".syd-base".into(), ".syd-delta".into()],
+            globs: Vec::new(),
+        };
+        for path in paths {
+            if path.contains(['*', '?']) {
+                matcher.globs.push(path.clone());
+                continue;
+            }
+            let path = config::expand_path(path);
+            let repo_path = match config.entries.iter().find(|entry| path.starts_with(&entry.path)) {
+                Some(entry) => entry.repo_path().join(path.strip_prefix(&entry.path).unwrap_or(&path)),
+                None => config::repo_path_for(&path),
+            };
+            let repo_path = repo_path.to_string_lossy().trim_end_matches('/').to_string();
+            matcher.prefixes.push(repo_path);
+        }
+        matcher
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.prefixes.iter().any(|prefix| match path.strip_prefix(prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || self.suffixes.iter().any(|suffix| rest == suffix),
+            None => false,
+        }) || self.globs.iter().any(|glob| match glob.contains('/') {
+            true => glob_matches(glob.as_bytes(), path.as_bytes()),
+            false => glob_matches(glob.as_bytes(), name.as_bytes()),
+        })
+    }
+}
+
+// `*` matches within one path component, `?` a single character.
+fn glob_matches(glob: &[u8], text: &[u8]) -> bool {
+    match (glob.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_matches(&glob[1..], text) || (!text.is_empty() && text[0] != b'/' && glob_matches(glob, &text[1..]))
+        }
+        (Some(b'?'), Some(&c)) if c != b'/' => glob_matches(&glob[1..], &text[1..]),
+        (Some(g), Some(c)) if g == c => glob_matches(&glob[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+// Filters a fast-export stream: file changes of matching paths are dropped,
+// and the texts are replaced in blobs. Everything else passes through as is.
+fn rewrite(config: &Config, scrub: &Scrub, stream: &[u8]) -> io::Result<(Vec<u8>, ScrubReport)> {
+    let matcher = Matcher::new(config, &scrub.paths);
+    let encrypted_suffix = format!(".{}", crypto::extension(config));
+    let mut report = ScrubReport {
+        folder: config.backup_folder(),
+        paths: BTreeSet::new(),
+        blobs: 0,
+        encrypted: BTreeSet::new(),
+        pushed: false,
+    };
+    let mut deltas = BTreeSet::new();
+    let mut out = Vec::with_capacity(stream.len());
+    let mut rest = stream;
+    let mut in_blob = false;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&byte| byte == b'\n').map_or(rest.len(), |newline| newline + 1);
+        let (line, tail) = rest.split_at(end);
+        rest = tail;
+        let text = String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line));
+        if let Some(length) = text.strip_prefix("data ") {
+            let length: usize = length
+                .parse()
+                .ok()
+                .filter(|length| *length <= rest.len())
+                .ok_or_else(|| io::Error::other(format!("unexpected fast-export line {:?}", text)))?;
+            let (data, tail) = rest.split_at(length);
+            rest = tail;
+            let mut data = data.to_vec();
+            if in_blob {
+                let replaced = scrub.texts.iter().fold(data.clone(), |data, text| replace(&data, text.as_bytes()));
+                if replaced != data {
+                    report.blobs += 1;
+                    data = replaced;
+                }
+            }
+            out.extend_from_slice(format!("data {}\n", data.len()).as_bytes());
+            out.extend_from_slice(&data);
+            continue;
+        }
+        if text == "blob" {
+            in_blob = true;
+        } else if text.starts_with("commit ") || text.starts_with("tag ") || text.starts_with("reset ") {
+            in_blob = false;
+        }
+        let changed = match text.split_once(' ') {
+            Some(("M", rest)) => rest.splitn(3, ' ').nth(2),
+            Some(("D", path)) => Some(path),
+            _ => None,
+        };
+        if let Some(path) = changed.map(unquote) {
+            if matcher.matches(&path) {
+                report.paths.insert(path);
+                continue;
+            }
+            let searched = !scrub.texts.is_empty();
+            if searched && (path.ends_with(BASE_SUFFIX) || path.ends_with(DELTA_SUFFIX)) {
+                deltas.insert(path);
+            } else if searched && path.ends_with(&encrypted_suffix) {
+                report.encrypted.insert(path);
+            }
+        }
+        out.extend_from_slice(line);
+    }
+    if !deltas.is_empty() {
+        let deltas: Vec<String> = deltas.into_iter().collect();
+        return Err(io::Error::other(format!(
+            "the text cannot be replaced in delta copies, where each version is built from the one before: {}; \
+             remove them from history by path as well",
+            deltas.join(", ")
+        )));
+    }
+    Ok((out, report))
+}
+
+fn replace(data: &[u8], text: &[u8]) -> Vec<u8> {
+    if text.is_empty() {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i..].starts_with(text) {
+            out.extend_from_slice(REPLACEMENT);
+            i += text.len();
+        } else {
+            out.push(data[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+// Git quotes paths with unusual characters C-style.
+fn unquote(path: &str) -> String {
+    let Some(quoted) = path.strip_prefix('"').and_then(|path| path.strip_suffix('"')) else {
+        return path.to_string();
+    };
+    let bytes = quoted.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let octal = quoted.get(i + 1..i + 4).and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (bytes[i + 1], octal) {
+            (b'0'..=b'3', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+                continue;
+            }
+            (b'n', _) => out.push(b'\n'),
+            (b't', _) => out.push(b'\t'),
+            (other, _) => out.push(other),
+        }
+        i += 2;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn config() -> Config {
+        let text = "[backup]\nfolder = \"/tmp/syd-scrub-test\"\n\n[[files]]\npath = \"~/.netrc\"\nencrypt = true\n";
+        let (settings, entries, repos) = config::parse(text).unwrap();
+        Config {
+            path: PathBuf::from("syd.toml"),
+            settings,
+            entries,
+            repos,
+        }
+    }
+
+    // One blob holding `token`, and a commit mentioning it in its message that
+    // changes the given paths.
+    fn stream(paths: &[&str]) -> Vec<u8> {
+        let blob = "machine example.com password token\n";
+        let message = "Backup token\n";
+        let mut stream = format!("blob\nmark :1\ndata {}\n{}\n", blob.len(), blob);
+        stream.push_str("reset refs/heads/main\ncommit refs/heads/main\nmark :2\n");
+        stream.push_str("author syd <syd@localhost> 0 +0000\ncommitter syd <syd@localhost> 0 +0000\n");
+        stream.push_str(&format!("data {}\n{}", message.len(), message));
+        for path in paths {
+            stream.push_str(&format!("M 100644 :1 {}\n", path));
+        }
+        stream.push_str("D gone\n\n");
+        stream.into_bytes()
+    }
+
+    #[test]
+    fn drops_tracked_paths_and_their_stored_variants() {
+        let scrub = Scrub {
+            paths: vec!["~/.netrc".to_string(), "*.pem".to_string()],
+            texts: Vec::new(),
+        };
+        let paths = [".netrc.gpg", ".netrc-old", "keys/site.pem", "\"with space\\t.pem\"", "keep"];
+        let (out, report) = rewrite(&config(), &scrub, &stream(&paths)).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let kept: Vec<&str> = out.lines().filter(|line| line.starts_with("M ") || line.starts_with("D ")).collect();
+        assert_eq!(kept, ["M 100644 :1 .netrc-old", "M 100644 :1 keep", "D gone"]);
+        let dropped: Vec<&str> = report.paths.iter().map(String::as_str).collect();
+        assert_eq!(dropped, [".netrc.gpg", "keys/site.pem", "with space\t.pem"]);
+        assert_eq!(report.blobs, 0);
+    }
+
+    #[test]
+    fn replaces_text_in_blobs_only() {
+        let scrub = Scrub {
+            paths: Vec::new(),
+            texts: vec!["token".to_string()],
+        };
+        let (out, report) = rewrite(&config(), &scrub, &stream(&["keep"])).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let blob = "machine example.com password ***REMOVED***\n";
+        assert!(out.starts_with(&format!("blob\nmark :1\ndata {}\n{}\n", blob.len(), blob)), "{}", out);
+        // Commit messages are framed the same way but left alone.
+        assert!(out.contains("data 13\nBackup token\nM 100644 :1 keep\n"), "{}", out);
+        assert_eq!(report.blobs, 1);
+        assert!(report.paths.is_empty());
+    }
+
+    #[test]
+    fn text_is_refused_while_delta_copies_remain() {
+        let mut scrub = Scrub {
+            paths: Vec::new(),
+            texts: vec!["token".to_string()],
+        };
+        let paths = ["big.syd-base", "big.syd-delta", ".netrc.gpg"];
+        let error = rewrite(&config(), &scrub, &stream(&paths)).err().unwrap();
+        assert!(error.to_string().contains("big.syd-base, big.syd-delta"), "{}", error);
+
+        // Dropping them by path as well lets the rest through.
+        scrub.paths.push("big*".to_string());
+        let (_, report) = rewrite(&config(), &scrub, &stream(&paths)).unwrap();
+        assert_eq!(report.paths.len(), 2);
+        let encrypted: Vec<&str> = report.encrypted.iter().map(String::as_str).collect();
+        assert_eq!(encrypted, [".netrc.gpg"]);
+
+        // Without `--text` nothing is searched, so nothing is reported.
+        scrub.texts.clear();
+        let (_, report) = rewrite(&config(), &scrub, &stream(&paths)).unwrap();
+        assert!(report.encrypted.is_empty());
+    }
+
+    #[test]
+    fn data_that_looks_like_commands_stays_data() {
+        let blob = "M 100644 :1 keep\ndata 3\n";
+        let stream = format!("blob\nmark :1\ndata {}\n{}commit refs/heads/main\n", blob.len(), blob);
+        let scrub = Scrub {
+            paths: vec!["keep".to_string()],
+            texts: Vec::new(),
+        };
+        let (out, report) = rewrite(&config(), &scrub, stream.as_bytes()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), stream);
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let scrub = Scrub::default();
+        assert!(rewrite(&config(), &scrub, b"blob\ndata 100\nshort").is_err());
+        assert!(rewrite(&config(), &scrub, b"blob\ndata many\n").is_err());
+    }
+
+    #[test]
+    fn globs_stay_within_a_component() {
+        assert!(glob_matches(b"*.pem", b"site.pem"));
+        assert!(glob_matches(b"*", b""));
+        assert!(glob_matches(b"id_?sa", b"id_rsa"));
+        assert!(glob_matches(b"keys/*.pem", b"keys/site.pem"));
+        assert!(!glob_matches(b"keys/*.pem", b"keys/old/site.pem"));
+        assert!(!glob_matches(b"*.pem", b"keys/site.pem"));
+        assert!(!glob_matches(b"?", b"/"));
+        assert!(!glob_matches(b"*.pem", b"site.pem.bak"));
+    }
+
+    #[test]
+    fn unquotes_c_style_paths() {
+        assert_eq!(unquote("plain name"), "plain name");
+        assert_eq!(unquote("\"tab\\there\""), "tab\there");
+        assert_eq!(unquote("\"quote\\\"and\\\\slash\""), "quote\"and\\slash");
+        assert_eq!(unquote("\"new\\nline\""), "new\nline");
+        assert_eq!(unquote("\"caf\\303\\251\""), "café");
+        assert_eq!(unquote("\"trailing\\\""), "trailing\\");
+    }
+}
@@ -0,0 +1,210 @@
+// `syd share` exports the entries marked `public = true` into a separate
+// folder, optionally a git repo of its own, that can be published. Files go
+// through the entry's filters as for a backup, so local regions and
+// `filter_lines` stay private.
+// `.syd-share` in the folder lists what the last export wrote; only those
+// files are ever removed, and a folder without it that is not empty is refused.
+use crate::config::{expand_path, Config, Value};
+use crate::editor;
+use crate::files;
+use crate::filter;
+use crate::git;
+use crate::json;
+use crate::log;
+use crate::push;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+const DEFAULT_SHARE_FOLDER: &str = "~/syd-public";
+const MARKER_FILE: &str = ".syd-share";
+const REDACTED: &[u8] = b"# (line removed by syd share)";
+
+#[derive(Debug, Default)]
+pub struct ShareReport {
+    pub files: usize,
+    pub redacted_lines: usize,
+    pub pushed: bool,
+}
+
+pub fn share_folder(config: &Config) -> PathBuf {
+    expand_path(config.settings.str("share.folder").unwrap_or(DEFAULT_SHARE_FOLDER))
+}
+
+pub fn share(config: &Config) -> io::Result<ShareReport> {
+    let folder = share_folder(config);
+    let remote = config.settings.str("share.remote").filter(|r| !r.is_empty());
+    let branch = config.settings.str("share.branch").unwrap_or(config.branch());
+    let redact: Vec<Vec<u8>> = config
+        .settings
+        .list("share.redact")
+        .iter()
+        .map(|pattern| pattern.to_lowercase().into_bytes())
+        .collect();
+
+    // The export is rebuilt from scratch every time, so entries that stopped
+    // being public disappear from it.
+    remove_previous_export(&folder)?;
+
+    let mut written = Vec::new();
+    let mut report = ShareReport::default();
+    for entry in config.entries.iter().filter(|entry| entry.options.bool("public").unwrap_or(false)) {
+        if entry.encrypted() {
+            log::warn(
+                &format!("not sharing {}: it is encrypted", entry.path.display()),
+                &[("path", json::string(&entry.path.to_string_lossy()))],
+            );
+            continue;
+        }
+        if !entry.path.exists() {
+            continue;
+        }
+        let destination = folder.join(entry.repo_path());
+        for relative in files::walk(&entry.path)? {
+            if !relative.as_os_str().is_empty() && editor::is_artifact(&relative) {
+                continue;
+            }
+            let source = files::join(&entry.path, &relative);
+            let content = filter::clean(entry, fs::read(&source)?)?;
+            let (content, redacted) = strip_secrets(&content, &redact);
+            let target = files::join(&destination, &relative);
+            files::write_file(&target, &content, &source)?;
+            written.push(target.strip_prefix(&folder).unwrap_or(&target).to_path_buf());
+            report.files += 1;
+            report.redacted_lines += redacted;
+        }
+    }
+
+    let list: String = written.iter().map(|path| format!("{}\n", path.to_string_lossy())).collect();
+    files::replace(&folder.join(MARKER_FILE), list.as_bytes())?;
+
+    if let Some(remote) = remote {
+        git::create_local_repo(&folder, branch, Some(remote))?;
+        exclude_marker(&folder)?;
+        git::add_all(&folder)?;
+        if git::has_staged_changes(&folder)? {
+            git::commit(&folder, &format!("Share from {}", git::hostname()))?;
+        }
+        // Pushing, and what it does when the branch is protected or over
+        // quota, is about the share repo, not the backup.
+        let mut share = config.clone();
+        share.settings.insert("backup.folder".to_string(), Value::Str(folder.to_string_lossy().into_owned()));
+        share.settings.insert("git.remote".to_string(), Value::Str(remote.to_string()));
+        share.settings.insert("git.branch".to_string(), Value::Str(branch.to_string()));
+        push::push(&share, &folder, branch)?;
+        report.pushed = true;
+    }
+    Ok(report)
+}
+
+// Removes the files the last export listed in the marker, and the
+// directories they leave empty.
+fn remove_previous_export(folder: &Path) -> io::Result<()> {
+    let marker = folder.join(MARKER_FILE);
+    let list = match fs::read_to_string(&marker) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if !folder.exists() {
+                return fs::create_dir_all(folder);
+            }
+            if fs::read_dir(folder)?.next().is_some() {
+                return Err(io::Error::other(format!(
+                    "{} exists and was not created by syd share (it has no {}); \
+                     set share.folder to a new or empty folder",
+                    folder.display(),
+                    MARKER_FILE
+                )));
+            }
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    for line in list.lines().filter(|line| !line.is_empty()) {
+        let relative = Path::new(line);
+        // The marker is the only thing trusted here, and only below the folder.
+        if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            continue;
+        }
+        let path = folder.join(relative);
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+        let mut parent = path.parent();
+        while let Some(dir) = parent.filter(|dir| *dir != folder) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+    Ok(())
+}
+
+// The marker is bookkeeping for this machine, not part of what is published.
+fn exclude_marker(folder: &Path) -> io::Result<()> {
+    let exclude = folder.join(".git/info/exclude");
+    let existing = fs::read_to_string(&exclude).unwrap_or_default();
+    if existing.lines().any(|line| line == MARKER_FILE) {
+        return Ok(());
+    }
+    if let Some(parent) = exclude.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&exclude)?;
+    writeln!(file, "{}", MARKER_FILE)
+}
+
+// Drops every line containing one of the `share.redact` patterns (compared
+// case-insensitively), leaving a marker so the gap is visible.
+fn strip_secrets(content: &[u8], patterns: &[Vec<u8>]) -> (Vec<u8>, usize) {
+    if patterns.is_empty() {
+        return (content.to_vec(), 0);
+    }
+    let mut out = Vec::with_capacity(content.len());
+    let mut redacted = 0;
+    for (index, line) in content.split(|&b| b == b'\n').enumerate() {
+        if index > 0 {
+            out.push(b'\n');
+        }
+        let lower = line.to_ascii_lowercase();
+        if patterns.iter().any(|pattern| contains(&lower, pattern)) {
+            out.extend_from_slice(REDACTED);
+            redacted += 1;
+        } else {
+            out.extend_from_slice(line);
+        }
+    }
+    (out, redacted)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previous_export_is_removed_only_when_marked() {
+        let folder = std::env::temp_dir().join(format!("syd-share-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(folder.join("conf/sub")).unwrap();
+        fs::write(folder.join("mine"), "keep").unwrap();
+        fs::write(folder.join("conf/sub/f"), "old").unwrap();
+        assert!(remove_previous_export(&folder).is_err());
+        assert!(folder.join("conf/sub/f").exists());
+
+        fs::write(folder.join(MARKER_FILE), "conf/sub/f\n../mine\n").unwrap();
+        remove_previous_export(&folder).unwrap();
+        assert!(!folder.join("conf").exists());
+        assert!(folder.join("mine").exists());
+        fs::remove_dir_all(&folder).unwrap();
+
+        remove_previous_export(&folder).unwrap();
+        assert!(folder.is_dir());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
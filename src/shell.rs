@@ -0,0 +1,74 @@
+// Snippets printed by `syd shell-init`, meant to be evaluated from the
+// shell's rc file. They run a quick local backup in the background at most
+// every $SYD_BACKUP_INTERVAL seconds (default 600) and when the shell exits;
+// pushing is left to the next regular `syd backup` or the daemon.
+
+const BASH: &str = r#"# eval "$(syd shell-init bash)" in ~/.bashrc
+_syd_backup() { (command syd backup --quiet --no-push >/dev/null 2>&1 &); }
+_syd_now() { echo "${EPOCHSECONDS:-$(date +%s)}"; }
+_syd_last_backup=$(_syd_now)
+_syd_precmd() {
+    local now
+    now=$(_syd_now)
+    if (( now - _syd_last_backup >= ${SYD_BACKUP_INTERVAL:-600} )); then
+        _syd_last_backup=$now
+        _syd_backup
+    fi
+}
+PROMPT_COMMAND="_syd_precmd${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+# Runs after an EXIT trap set earlier instead of replacing it.
+_syd_trap_exit() {
+    eval "set -- $(trap -p EXIT)"
+    case "$3" in
+        *_syd_backup*) ;;
+        "") trap _syd_backup EXIT ;;
+        *) trap "$3"$'\n'_syd_backup EXIT ;;
+    esac
+}
+_syd_trap_exit
+unset -f _syd_trap_exit
+"#;
+
+const ZSH: &str = r#"# eval "$(syd shell-init zsh)" in ~/.zshrc
+zmodload zsh/datetime
+autoload -Uz add-zsh-hook
+_syd_backup() { command syd backup --quiet --no-push >/dev/null 2>&1 &! }
+typeset -g _syd_last_backup=$EPOCHSECONDS
+_syd_precmd() {
+    if (( EPOCHSECONDS - _syd_last_backup >= ${SYD_BACKUP_INTERVAL:-600} )); then
+        _syd_last_backup=$EPOCHSECONDS
+        _syd_backup
+    fi
+}
+add-zsh-hook precmd _syd_precmd
+add-zsh-hook zshexit _syd_backup
+"#;
+
+const FISH: &str = r#"# syd shell-init fish | source in ~/.config/fish/config.fish
+function _syd_backup
+    command syd backup --quiet --no-push >/dev/null 2>&1 &
+    disown
+end
+set -g _syd_last_backup (date +%s)
+function _syd_precmd --on-event fish_prompt
+    set -l interval 600
+    set -q SYD_BACKUP_INTERVAL; and set interval $SYD_BACKUP_INTERVAL
+    set -l now (date +%s)
+    if test (math $now - $_syd_last_backup) -ge $interval
+        set -g _syd_last_backup $now
+        _syd_backup
+    end
+end
+function _syd_exit --on-event fish_exit
+    _syd_backup
+end
+"#;
+
+pub fn snippet(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(BASH),
+        "zsh" => Some(ZSH),
+        "fish" => Some(FISH),
+        _ => None,
+    }
+}
@@ -0,0 +1,43 @@
+use simple_expand_tilde::*;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub fn state_dir() -> io::Result<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => expand_tilde("~/.local/state").ok_or_else(|| io::Error::other("Failed to expand tilde"))?,
+    };
+    let dir = base.join("syd");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// Small `key=value` files in the state directory, used for counters and
+// bookkeeping that has to survive between runs.
+pub fn load(name: &str) -> io::Result<BTreeMap<String, String>> {
+    let path = state_dir()?.join(name);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect())
+}
+
+pub fn save(name: &str, values: &BTreeMap<String, String>) -> io::Result<()> {
+    let path = state_dir()?.join(name);
+    let mut text = String::new();
+    for (key, value) in values {
+        text.push_str(&format!("{}={}\n", key, value));
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(tmp, path)
+}
@@ -0,0 +1,107 @@
+// `syd stats --repo`: where the size of a backup repo comes from. Every blob
+// ever committed is attributed to the path it was first seen at, so files
+// that changed often or were deleted long ago still show up, together with
+// what dropping their history or moving them to Git LFS would save.
+use crate::config::Config;
+use crate::exec;
+use crate::git;
+use crate::store;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const TOP_OFFENDERS: usize = 10;
+
+#[derive(Default)]
+pub struct FileStats {
+    pub path: String,
+    pub versions: usize,
+    // Size of the version at HEAD, 0 when the file is gone.
+    pub current: u64,
+    // Compressed size of all versions in the object store.
+    pub disk: u64,
+    // What rewriting history to keep only the version at HEAD would save.
+    pub prune_savings: u64,
+}
+
+pub struct RepoStats {
+    pub folder: PathBuf,
+    pub commits: usize,
+    // Size of the object store, loose and packed.
+    pub disk: u64,
+    pub files: Vec<FileStats>,
+}
+
+pub fn stats(config: &Config) -> io::Result<Vec<RepoStats>> {
+    let mut all = Vec::new();
+    for repo in config.repo_configs()? {
+        let folder = repo.backup_folder();
+        if !store::is_git(&repo) || !git::succeeds(&folder, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+            continue;
+        }
+        all.push(repo_stats(&folder)?);
+    }
+    Ok(all)
+}
+
+fn repo_stats(folder: &Path) -> io::Result<RepoStats> {
+    let commits = git::git(folder, &["rev-list", "--count", "--all"])?.parse().unwrap_or(0);
+    let mut disk = 0;
+    for line in git::git(folder, &["count-objects", "-v"])?.lines() {
+        if let Some(("size" | "size-pack", kib)) = line.split_once(": ") {
+            disk += kib.trim().parse::<u64>().unwrap_or(0) * 1024;
+        }
+    }
+
+    // `rev-list --objects` names each object once, after the first path it
+    // appears at; trees and commits have their own lines without a path.
+    let objects = git::git(folder, &["rev-list", "--objects", "--all"])?;
+    let mut command = git::command(folder);
+    command.args(["cat-file", "--batch-check=%(objecttype) %(objectname) %(objectsize) %(objectsize:disk) %(rest)"]);
+    let output = exec::pipe(command, format!("{}\n", objects).as_bytes())?;
+    let mut by_path: BTreeMap<String, FileStats> = BTreeMap::new();
+    let mut blob_disk: HashMap<String, u64> = HashMap::new();
+    for line in String::from_utf8_lossy(&output).lines() {
+        let mut fields = line.splitn(5, ' ');
+        let (Some("blob"), Some(id), Some(_), Some(on_disk), Some(path)) =
+            (fields.next(), fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let on_disk: u64 = on_disk.parse().unwrap_or(0);
+        blob_disk.insert(id.to_string(), on_disk);
+        let file = by_path.entry(path.to_string()).or_default();
+        file.versions += 1;
+        file.disk += on_disk;
+    }
+
+    // The version at HEAD is kept by either clean-up.
+    let mut current_disk: HashMap<String, u64> = HashMap::new();
+    for line in git::git(folder, &["ls-tree", "-r", "-l", "HEAD"])?.lines() {
+        let Some((meta, path)) = line.split_once('\t') else {
+            continue;
+        };
+        let fields: Vec<&str> = meta.split_whitespace().collect();
+        if let ["100644" | "100755" | "120000", "blob", id, size] = fields[..] {
+            let file = by_path.entry(path.to_string()).or_default();
+            file.current = size.parse().unwrap_or(0);
+            *current_disk.entry(path.to_string()).or_default() += blob_disk.get(id).copied().unwrap_or(0);
+        }
+    }
+    let mut files: Vec<FileStats> = by_path
+        .into_iter()
+        .filter(|(_, file)| file.versions > 0)
+        .map(|(path, mut file)| {
+            file.prune_savings = file.disk.saturating_sub(current_disk.get(&path).copied().unwrap_or(0));
+            file.path = path;
+            file
+        })
+        .collect();
+    files.sort_by(|a, b| b.disk.cmp(&a.disk).then_with(|| a.path.cmp(&b.path)));
+    Ok(RepoStats {
+        folder: folder.to_path_buf(),
+        commits,
+        disk,
+        files,
+    })
+}
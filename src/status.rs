@@ -0,0 +1,135 @@
+// `syd status`: which tracked files differ from the backup repo, without
+// touching the network. Files the stat cache knows to be unchanged since the
+// last backup are not read, so this is cheap enough for a shell prompt.
+use crate::cache::{self, StatCache};
+use crate::config::Config;
+use crate::files;
+use crate::filter;
+use crate::git;
+use crate::metadata::{Origin, Origins};
+use crate::owner;
+use crate::push;
+use crate::storage;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Modified,
+    New,
+    Missing,
+}
+
+impl Change {
+    pub fn label(self) -> &'static str {
+        match self {
+            Change::Modified => "modified",
+            Change::New => "new",
+            Change::Missing => "missing",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StatusReport {
+    pub changes: Vec<(Change, PathBuf)>,
+    // Commits made locally (e.g. with `--no-push`) that the remote lacks.
+    pub unpushed: usize,
+}
+
+// The host and date of the last backup of every stored file, by home path.
+pub fn origins(config: &Config) -> io::Result<BTreeMap<PathBuf, Origin>> {
+    let mut origins = BTreeMap::new();
+    for repo in config.repo_configs()? {
+        let folder = repo.backup_folder();
+        if !folder.join(".git").exists() {
+            continue;
+        }
+        let history = Origins::load(&folder)?;
+        for entry in &repo.entries {
+            let destination = folder.join(entry.repo_path());
+            for (target, _) in storage::stored_files(&repo, entry, &folder)? {
+                let relative = target.strip_prefix(&entry.path).unwrap_or(Path::new(""));
+                let plain = files::join(&destination, relative);
+                let names: Vec<PathBuf> = storage::repo_files(&repo, entry, &plain)
+                    .into_iter()
+                    .filter_map(|name| name.strip_prefix(&folder).ok().map(Path::to_path_buf))
+                    .collect();
+                if let Some(origin) = history.get(&names) {
+                    origins.insert(target, origin.clone());
+                }
+            }
+        }
+    }
+    Ok(origins)
+}
+
+// The backed-up content of one home path, decrypted or rebuilt as needed.
+pub fn stored_content(config: &Config, path: &Path) -> io::Result<Option<Vec<u8>>> {
+    for repo in config.repo_configs()? {
+        let folder = repo.backup_folder();
+        for entry in repo.entries.iter().filter(|entry| path.starts_with(&entry.path)) {
+            if let Some((_, source)) = storage::stored_files(&repo, entry, &folder)?
+                .into_iter()
+                .find(|(target, _)| target == path)
+            {
+                return storage::load(&repo, entry, &source).map(Some);
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn status(config: &Config) -> io::Result<StatusReport> {
+    let mut report = StatusReport::default();
+    let cache = StatCache::load()?;
+    for repo in config.repo_configs()? {
+        let folder = repo.backup_folder();
+        for entry in &repo.entries {
+            if entry.options.str("submodule").is_some() {
+                continue;
+            }
+            let destination = folder.join(entry.repo_path());
+            let fingerprint = cache::fingerprint(&repo, entry);
+            if entry.path.exists() {
+                for relative in owner::walk(owner::of(entry), &entry.path)? {
+                    let source = files::join(&entry.path, &relative);
+                    let target = files::join(&destination, &relative);
+                    if !storage::is_stored(&repo, entry, &target) {
+                        report.changes.push((Change::New, source));
+                        continue;
+                    }
+                    if cache.is_fresh(&source, &fingerprint) {
+                        continue;
+                    }
+                    let content = filter::clean(entry, owner::read(owner::of(entry), &source)?)?;
+                    if !storage::is_current(&repo, entry, &content, &target)? {
+                        report.changes.push((Change::Modified, source));
+                    }
+                }
+            }
+            for (path, _) in storage::stored_files(&repo, entry, &folder)? {
+                if !path.exists() {
+                    report.changes.push((Change::Missing, path));
+                }
+            }
+        }
+        if repo.remote().is_some() && folder.join(".git").exists() {
+            let upstream = format!("origin/{}", repo.branch());
+            if git::succeeds(&folder, &["rev-parse", "--verify", "--quiet", &upstream])? {
+                let mut args = vec!["rev-list".to_string(), "--count".to_string(), "HEAD".to_string()];
+                args.push(format!("^{}", upstream));
+                // Commits waiting on the topic branch for a protected branch
+                // to take them have been pushed as far as syd can.
+                let topic = format!("origin/{}", push::topic_branch(&repo));
+                if git::succeeds(&folder, &["rev-parse", "--verify", "--quiet", &topic])? {
+                    args.push(format!("^{}", topic));
+                }
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                report.unpushed += git::git(&folder, &args)?.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    Ok(report)
+}
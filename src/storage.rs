@@ -0,0 +1,202 @@
+// How an entry's files are represented in the backup repo: plain copies,
+// encrypted copies (`name.gpg`), or a base copy plus a binary delta
+// (`name.syd-base` and `name.syd-delta`) for large, frequently changing files.
+use crate::config::{Config, Entry};
+use crate::crypto;
+use crate::delta;
+use crate::files;
+use crate::filter;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+pub const BASE_SUFFIX: &str = ".syd-base";
+pub const DELTA_SUFFIX: &str = ".syd-delta";
+// Once the delta grows past this share of the file, a fresh base is stored.
+const DEFAULT_REBASE_PERCENT: i64 = 50;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Storage {
+    Plain,
+    Encrypted,
+    Delta,
+}
+
+pub fn storage(entry: &Entry) -> Storage {
+    if entry.encrypted() {
+        Storage::Encrypted
+    } else if entry.options.bool("delta").unwrap_or(false) {
+        Storage::Delta
+    } else {
+        Storage::Plain
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// What stored copies add to the plain name; deltas add DELTA_SUFFIX as well.
+pub fn suffix(config: &Config, storage: Storage) -> String {
+    match storage {
+        Storage::Plain => String::new(),
+        Storage::Encrypted => format!(".{}", crypto::extension(config)),
+        Storage::Delta => BASE_SUFFIX.to_string(),
+    }
+}
+
+// Writes `content`, read from `source`, into the repo at `repo_file` (the
+// plain path), returning the number of bytes written or None when the stored
+// copy is current.
+pub fn store(
+    config: &Config,
+    entry: &Entry,
+    recipients: &[String],
+    source: &Path,
+    content: &[u8],
+    repo_file: &Path,
+) -> io::Result<Option<u64>> {
+    if is_current(config, entry, content, repo_file)? {
+        return Ok(None);
+    }
+    match storage(entry) {
+        Storage::Plain => {
+            files::replace_file(repo_file, content, source)?;
+            Ok(Some(content.len() as u64))
+        }
+        Storage::Encrypted => {
+            let target = crypto::encrypted_name(config, repo_file);
+            crypto::write_encrypted(config, recipients, &target, content).map(Some)
+        }
+        Storage::Delta => {
+            let base_file = with_suffix(repo_file, BASE_SUFFIX);
+            let delta_file = with_suffix(repo_file, DELTA_SUFFIX);
+            if let Ok(base) = fs::read(&base_file) {
+                let delta = delta::diff(&base, content);
+                let limit = entry.options.int("delta_rebase_percent").unwrap_or(DEFAULT_REBASE_PERCENT);
+                if (delta.len() as u64) * 100 <= (content.len() as u64) * limit.max(0) as u64 {
+                    files::replace(&delta_file, &delta)?;
+                    return Ok(Some(delta.len() as u64));
+                }
+            }
+            files::replace_file(&base_file, content, source)?;
+            if delta_file.exists() {
+                fs::remove_file(&delta_file)?;
+            }
+            Ok(Some(content.len() as u64))
+        }
+    }
+}
+
+// Whether the stored copy of `repo_file` (the plain path) holds `content`.
+// Encrypted copies are checked against the record of what was encrypted, so
+// nothing has to be decrypted. Changes confined to lines matching the entry's
+// `ignore_content_patterns` do not count.
+pub fn is_current(config: &Config, entry: &Entry, content: &[u8], repo_file: &Path) -> io::Result<bool> {
+    let current = match storage(entry) {
+        Storage::Plain => fs::read(repo_file).ok().as_deref() == Some(content),
+        Storage::Encrypted => crypto::is_current(&crypto::encrypted_name(config, repo_file), content)?,
+        Storage::Delta => match fs::read(with_suffix(repo_file, BASE_SUFFIX)) {
+            Ok(base) => read_delta(&base, &with_suffix(repo_file, DELTA_SUFFIX))? == content,
+            Err(_) => false,
+        },
+    };
+    let patterns = entry.options.list("ignore_content_patterns");
+    if current || patterns.is_empty() || !is_stored(config, entry, repo_file) {
+        return Ok(current);
+    }
+    let stored = load(config, entry, &with_suffix(repo_file, &suffix(config, storage(entry))))?;
+    filter::same_ignoring(&patterns, &stored, content)
+}
+
+// Gives the stored copy the executable bit of `source`, which is what git
+// records of a file's mode. Returns whether it changed.
+pub fn update_mode(config: &Config, entry: &Entry, source: &Path, repo_file: &Path) -> io::Result<bool> {
+    let stored = with_suffix(repo_file, &suffix(config, storage(entry)));
+    let permissions = fs::metadata(source)?.permissions();
+    let executable = |mode: u32| mode & 0o111 != 0;
+    if executable(permissions.mode()) == executable(fs::metadata(&stored)?.permissions().mode()) {
+        return Ok(false);
+    }
+    fs::set_permissions(&stored, permissions)?;
+    Ok(true)
+}
+
+// The files in the repo that make up the stored copy of `repo_file`.
+pub fn repo_files(config: &Config, entry: &Entry, repo_file: &Path) -> Vec<PathBuf> {
+    match storage(entry) {
+        Storage::Delta => vec![with_suffix(repo_file, BASE_SUFFIX), with_suffix(repo_file, DELTA_SUFFIX)],
+        storage => vec![with_suffix(repo_file, &suffix(config, storage))],
+    }
+}
+
+// Whether the repo has a copy of `repo_file` (the plain path) at all.
+pub fn is_stored(config: &Config, entry: &Entry, repo_file: &Path) -> bool {
+    with_suffix(repo_file, &suffix(config, storage(entry))).is_file()
+}
+
+fn read_delta(base: &[u8], delta_file: &Path) -> io::Result<Vec<u8>> {
+    match fs::read(delta_file) {
+        Ok(delta) => delta::apply(base, &delta),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(base.to_vec()),
+        Err(e) => Err(e),
+    }
+}
+
+// The files stored for an entry as (home path, repo file) pairs.
+pub fn stored_files(config: &Config, entry: &Entry, folder: &Path) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let storage = storage(entry);
+    let suffix = suffix(config, storage);
+    let plain = folder.join(entry.repo_path());
+    let single = with_suffix(&plain, &suffix);
+    if !suffix.is_empty() && single.is_file() {
+        return Ok(vec![(entry.path.clone(), single)]);
+    }
+    if !plain.exists() {
+        return Ok(Vec::new());
+    }
+    let mut stored = Vec::new();
+    for relative in files::walk(&plain)? {
+        let name = relative.to_string_lossy();
+        let target = match name.strip_suffix(&suffix) {
+            Some(stripped) if !suffix.is_empty() => PathBuf::from(stripped),
+            _ if storage == Storage::Plain => relative.clone(),
+            _ => continue,
+        };
+        stored.push((files::join(&entry.path, &target), files::join(&plain, &relative)));
+    }
+    Ok(stored)
+}
+
+// The original content of a repo file listed by `stored_files`.
+pub fn load(config: &Config, entry: &Entry, repo_file: &Path) -> io::Result<Vec<u8>> {
+    let stored = fs::read(repo_file)?;
+    match storage(entry) {
+        Storage::Plain => Ok(stored),
+        Storage::Encrypted => decode(config, Storage::Encrypted, &stored, None)
+            .map_err(|e| io::Error::other(format!("decrypting {}: {}", repo_file.display(), e))),
+        Storage::Delta => {
+            let plain = repo_file.to_string_lossy();
+            let plain = plain.strip_suffix(BASE_SUFFIX).unwrap_or(&plain);
+            match fs::read(with_suffix(Path::new(plain), DELTA_SUFFIX)) {
+                Ok(delta) => decode(config, Storage::Delta, &stored, Some(&delta)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(stored),
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+// The original content from the bytes of a stored copy and, for a delta
+// entry, the delta on top of its base if there is one. `syd mount` reads
+// history through this, where there are no files to `load`.
+pub fn decode(config: &Config, storage: Storage, stored: &[u8], delta: Option<&[u8]>) -> io::Result<Vec<u8>> {
+    match (storage, delta) {
+        (Storage::Encrypted, _) => crypto::decrypt(config, stored),
+        (Storage::Delta, Some(delta)) => delta::apply(stored, delta),
+        _ => Ok(stored.to_vec()),
+    }
+}
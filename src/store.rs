@@ -0,0 +1,164 @@
+// Where a backup repo keeps its history. Git is the default and the only
+// backend with trust checks, push windows, conflict reports, drift, stats,
+// mount and try. `vcs = "command"` on a repo runs shell commands from the
+// config instead, for the few steps backups and restores need, so a repo can
+// live in Fossil, Mercurial or anything else with a command line. They run
+// with `sh -c` in the backup folder, with SYD_MESSAGE, SYD_REMOTE and
+// SYD_BRANCH set.
+use crate::config::Config;
+use crate::conflicts;
+use crate::exec;
+use crate::git;
+use crate::push;
+use crate::trust;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+pub trait Store {
+    // Makes the backup folder a checkout before the first backup.
+    fn init(&self) -> io::Result<()>;
+    // Brings the checkout up to date with the remote before a restore.
+    fn pull(&self, accept_rewrite: bool) -> io::Result<()>;
+    // Records everything in the folder, false when nothing changed.
+    fn commit(&self, message: &str) -> io::Result<bool>;
+    // False when there is nowhere to push to.
+    fn push(&self) -> io::Result<bool>;
+}
+
+pub fn open(config: &Config) -> io::Result<Box<dyn Store + '_>> {
+    match config.settings.str("backup.vcs").unwrap_or("git") {
+        "git" => Ok(Box::new(GitStore { config })),
+        "command" => Ok(Box::new(CommandStore { config })),
+        other => Err(io::Error::other(format!(
+            "unknown vcs {:?} for {}, expected \"git\" or \"command\"",
+            other,
+            config.backup_folder().display()
+        ))),
+    }
+}
+
+pub fn is_git(config: &Config) -> bool {
+    config.settings.str("backup.vcs").unwrap_or("git") == "git"
+}
+
+struct GitStore<'a> {
+    config: &'a Config,
+}
+
+impl Store for GitStore<'_> {
+    fn init(&self) -> io::Result<()> {
+        git::create_local_repo(&self.config.backup_folder(), self.config.branch(), self.config.remote())
+    }
+
+    // Fetches and fast-forwards once the incoming history passed the checks
+    // in `trust`.
+    fn pull(&self, accept_rewrite: bool) -> io::Result<()> {
+        let config = self.config;
+        let Some(remote) = config.remote() else {
+            return Ok(());
+        };
+        let folder = config.backup_folder();
+        if !folder.join(".git").exists() {
+            fs::create_dir_all(&folder)?;
+            git::create_local_repo(&folder, config.branch(), Some(remote))?;
+        }
+        git::fetch(&folder)?;
+        trust::verify_incoming(config, &folder, accept_rewrite)?;
+        if accept_rewrite {
+            // The local history still has the commits the remote dropped.
+            let upstream = format!("origin/{}", config.branch());
+            git::git(&folder, &["checkout", "--quiet", "-B", config.branch(), &upstream])?;
+            return git::update_submodules(&folder);
+        }
+        git::fast_forward(&folder, config.branch())?;
+        git::update_submodules(&folder)
+    }
+
+    fn commit(&self, message: &str) -> io::Result<bool> {
+        let folder = self.config.backup_folder();
+        git::add_all(&folder)?;
+        if !git::has_staged_changes(&folder)? {
+            return Ok(false);
+        }
+        git::commit(&folder, message)?;
+        Ok(true)
+    }
+
+    fn push(&self) -> io::Result<bool> {
+        let config = self.config;
+        if config.remote().is_none() {
+            return Ok(false);
+        }
+        let folder = config.backup_folder();
+        git::fetch(&folder)?;
+        trust::verify_incoming(config, &folder, false)?;
+        conflicts::sync_with_remote(&folder, config.branch())?;
+        push::push(config, &folder, config.branch())?;
+        trust::remember_remote_head(config, &folder)?;
+        Ok(true)
+    }
+}
+
+// `vcs_commit` is the only required command and has to add new files too;
+// without `vcs_status` every backup commits.
+struct CommandStore<'a> {
+    config: &'a Config,
+}
+
+impl CommandStore<'_> {
+    fn command(&self, key: &str) -> Option<&str> {
+        self.config.settings.str(&format!("backup.{}", key)).filter(|command| !command.is_empty())
+    }
+
+    fn run(&self, key: &str, message: &str) -> io::Result<Vec<u8>> {
+        let Some(script) = self.command(key) else {
+            return Err(io::Error::other(format!("vcs = \"command\" needs {} to be set", key)));
+        };
+        let folder = self.config.backup_folder();
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script).current_dir(&folder);
+        command.env("SYD_MESSAGE", message);
+        command.env("SYD_REMOTE", self.config.remote().unwrap_or(""));
+        command.env("SYD_BRANCH", self.config.branch());
+        exec::pipe(command, b"").map_err(|e| {
+            io::Error::other(format!("{} `{}` in {} failed: {}", key, script, folder.display(), e))
+        })
+    }
+}
+
+impl Store for CommandStore<'_> {
+    // `vcs_init` runs in the still empty folder, e.g. to clone into it.
+    fn init(&self) -> io::Result<()> {
+        let folder = self.config.backup_folder();
+        if self.command("vcs_init").is_some() && fs::read_dir(folder)?.next().is_none() {
+            self.run("vcs_init", "")?;
+        }
+        Ok(())
+    }
+
+    fn pull(&self, _accept_rewrite: bool) -> io::Result<()> {
+        if self.command("vcs_pull").is_none() {
+            return Ok(());
+        }
+        fs::create_dir_all(self.config.backup_folder())?;
+        self.init()?;
+        self.run("vcs_pull", "").map(|_| ())
+    }
+
+    fn commit(&self, message: &str) -> io::Result<bool> {
+        let unchanged = |status: Vec<u8>| status.iter().all(u8::is_ascii_whitespace);
+        if self.command("vcs_status").is_some() && unchanged(self.run("vcs_status", message)?) {
+            return Ok(false);
+        }
+        self.run("vcs_commit", message)?;
+        Ok(true)
+    }
+
+    fn push(&self) -> io::Result<bool> {
+        match self.command("vcs_push") {
+            Some(_) => self.run("vcs_push", "").map(|_| true),
+            None => Ok(false),
+        }
+    }
+}
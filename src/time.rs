@@ -0,0 +1,230 @@
+use std::env;
+use std::io;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A fixed "now" from $SYD_NOW or `clock.now`, as unix seconds or RFC 3339,
+// for test runs and reproducible images: syd then dates its commits, the
+// times it prints and logs, and push windows by it instead of the wall clock.
+// Local bookkeeping such as undo journals, their retention and log rotation
+// keeps to the wall clock (`wall_now`). The variable wins over the config. 0
+// when unset.
+static FIXED: AtomicU64 = AtomicU64::new(0);
+
+// Called with `clock.now` whenever the config is loaded.
+pub fn init(configured: Option<&str>) -> io::Result<()> {
+    let (name, value) = match env::var("SYD_NOW") {
+        Ok(value) if !value.is_empty() => ("SYD_NOW", value),
+        _ => match configured {
+            Some(value) => ("clock.now", value.to_string()),
+            None => {
+                FIXED.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+        },
+    };
+    let now = parse_time(&value)
+        .ok_or_else(|| io::Error::other(format!("invalid {} {:?}, expected unix seconds or RFC 3339", name, value)))?;
+    FIXED.store(now, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn fixed_now() -> Option<u64> {
+    match FIXED.load(Ordering::Relaxed) {
+        // Commands that run without a config still see $SYD_NOW.
+        0 => {
+            static FROM_ENV: OnceLock<Option<u64>> = OnceLock::new();
+            *FROM_ENV.get_or_init(|| env::var("SYD_NOW").ok().and_then(|value| parse_time(&value)))
+        }
+        fixed => Some(fixed),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    fixed_now().unwrap_or_else(wall_now)
+}
+
+pub fn wall_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// Unix seconds, optionally prefixed with `@`, or `YYYY-MM-DDTHH:MM:SS` with
+// `Z` or a `+HH:MM`/`-HH:MM` offset.
+pub fn parse_time(value: &str) -> Option<u64> {
+    let value = value.trim();
+    // 0 would read as "unset".
+    if let Ok(seconds) = value.strip_prefix('@').unwrap_or(value).parse::<u64>() {
+        return (seconds > 0).then_some(seconds);
+    }
+    let (date, clock) = value.split_once(['T', ' '])?;
+    let mut date = date.split('-');
+    let (year, month, day): (i64, u32, u32) =
+        (date.next()?.parse().ok()?, date.next()?.parse().ok()?, date.next()?.parse().ok()?);
+    let (clock, offset) = match clock.strip_suffix('Z') {
+        Some(clock) => (clock, 0),
+        None => {
+            let split = clock.rfind(['+', '-'])?;
+            let sign = if clock[split..].starts_with('-') { -1 } else { 1 };
+            (&clock[..split], sign * i64::from(parse_clock(&clock[split + 1..])?) * 60)
+        }
+    };
+    let mut clock = clock.split(':');
+    let (hours, minutes): (i64, i64) = (clock.next()?.parse().ok()?, clock.next()?.parse().ok()?);
+    let seconds: i64 = clock.next().map_or(Some(0), |seconds| seconds.parse().ok())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    // Catches days past the end of the month, such as February 30.
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    let unix = days * 86_400 + hours * 3600 + minutes * 60 + seconds - offset;
+    u64::try_from(unix).ok().filter(|unix| *unix > 0)
+}
+
+// File modification times as `secs.nanos` since the epoch.
+pub fn format_mtime(mtime: SystemTime) -> String {
+    let since = mtime.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", since.as_secs(), since.subsec_nanos())
+}
+
+pub fn parse_mtime(value: &str) -> Option<SystemTime> {
+    let (secs, nanos) = value.split_once('.')?;
+    Some(UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+}
+
+// Accepts plain seconds or a number with an s/m/h/d/w suffix, e.g. `90m`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let factor = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(number * factor))
+}
+
+pub fn format_rfc3339(unix: u64) -> String {
+    let days = (unix / 86_400) as i64;
+    let seconds = unix % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// Howard Hinnant's days-to-civil algorithm, valid for the proleptic
+// Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Minutes since local midnight. std has no notion of time zones, so the
+// system's date(1) is asked.
+pub fn local_minute_of_day() -> io::Result<u32> {
+    let mut command = Command::new("date");
+    if let Some(now) = fixed_now() {
+        command.arg("-d").arg(format!("@{}", now));
+    }
+    let output = command.arg("+%H:%M").output()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    parse_clock(text.trim()).ok_or_else(|| io::Error::other(format!("unexpected date output {:?}", text.trim())))
+}
+
+// Parses `HH:MM` into minutes since midnight.
+pub fn parse_clock(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_unix_seconds() {
+        assert_eq!(parse_time("1700000000"), Some(1_700_000_000));
+        assert_eq!(parse_time(" @1700000000 "), Some(1_700_000_000));
+        assert_eq!(parse_time("0"), None);
+        assert_eq!(parse_time("@0"), None);
+        assert_eq!(parse_time("-5"), None);
+    }
+
+    #[test]
+    fn parses_rfc3339_with_offsets() {
+        assert_eq!(parse_time("2023-11-14T22:13:20Z"), Some(1_700_000_000));
+        assert_eq!(parse_time("2023-11-14 22:13:20Z"), Some(1_700_000_000));
+        assert_eq!(parse_time("2023-11-15T00:13:20+02:00"), Some(1_700_000_000));
+        assert_eq!(parse_time("2023-11-14T17:13:20-05:00"), Some(1_700_000_000));
+        assert_eq!(parse_time("2023-11-14T22:13Z"), Some(1_699_999_980));
+        assert_eq!(parse_time("2024-02-29T00:00:00Z"), Some(1_709_164_800));
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for value in [
+            "",
+            "yesterday",
+            "2023-11-14",
+            "2023-11-14T22:13:20",
+            "2023-13-01T00:00:00Z",
+            "2023-02-29T00:00:00Z",
+            "2023-04-31T00:00:00Z",
+            "2023-11-00T00:00:00Z",
+            "2023-11-14T24:00:00Z",
+            "2023-11-14T22:60:00Z",
+            "2023-11-14T22:13:20+25:00",
+        ] {
+            assert_eq!(parse_time(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn days_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in [-1000, 0, 11_016, 11_017, 19_675, 100_000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(format_rfc3339(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+}
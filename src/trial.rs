@@ -0,0 +1,111 @@
+// `syd try <branch>` restores another branch of the backup repo from a
+// temporary worktree, and `syd try --end` puts the previous files back.
+use crate::config::{Config, Value};
+use crate::git;
+use crate::restore::{self, RestoreReport};
+use crate::state;
+use crate::store;
+use crate::trust;
+use crate::undo;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const TRY_STATE: &str = "try";
+
+// The branch currently being tried, if any.
+pub fn active() -> io::Result<Option<String>> {
+    Ok(state::load(TRY_STATE)?.get("branch").cloned())
+}
+
+pub fn start(config: &Config, branch: &str) -> io::Result<RestoreReport> {
+    if let Some(active) = active()? {
+        return Err(io::Error::other(format!(
+            "already trying {}, run `syd try --end` first",
+            active
+        )));
+    }
+    restore::update_backup_folder(config, false)?;
+    let repo = config
+        .repo_configs()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| io::Error::other("no backup repo configured"))?;
+    if !store::is_git(&repo) {
+        return Err(io::Error::other("syd try needs a git backup repo"));
+    }
+    let folder = repo.backup_folder();
+
+    // The branch goes through the same checks as anything else pulled in.
+    let mut trial = repo.clone();
+    trial.settings.insert("git.branch".to_string(), Value::Str(branch.to_string()));
+    let upstream = format!("origin/{}", branch);
+    let local = format!("refs/heads/{}", branch);
+    let has_upstream = git::succeeds(&folder, &["rev-parse", "--verify", "--quiet", &upstream])?;
+    let revision = if repo.remote().is_some() && has_upstream {
+        trust::verify_incoming(&trial, &folder, false)?;
+        upstream
+    } else if git::succeeds(&folder, &["rev-parse", "--verify", "--quiet", &local])? {
+        branch.to_string()
+    } else {
+        return Err(io::Error::other(format!("the backup repo has no branch {:?}", branch)));
+    };
+
+    let worktree = worktree_dir()?;
+    if worktree.exists() {
+        fs::remove_dir_all(&worktree)?;
+        git::git(&folder, &["worktree", "prune"])?;
+    }
+    git::git(
+        &folder,
+        &["worktree", "add", "--quiet", "--detach", &worktree.to_string_lossy(), &revision],
+    )?;
+    trial.settings.insert("backup.folder".to_string(), Value::Str(worktree.to_string_lossy().into_owned()));
+
+    let report = match restore::restore_dotfiles(&trial, &[], None) {
+        Ok(report) => report,
+        Err(e) => {
+            remove_worktree(&folder)?;
+            return Err(e);
+        }
+    };
+    let mut values = BTreeMap::new();
+    values.insert("branch".to_string(), branch.to_string());
+    values.insert("folder".to_string(), folder.to_string_lossy().into_owned());
+    if let Some(journal) = &report.journal {
+        values.insert("journal".to_string(), journal.to_string_lossy().into_owned());
+    }
+    state::save(TRY_STATE, &values)?;
+    Ok(report)
+}
+
+// Reverts the files written by `start` and removes the worktree, returning
+// the branch that was tried and the number of files put back.
+pub fn end() -> io::Result<(String, usize)> {
+    let values = state::load(TRY_STATE)?;
+    let Some(branch) = values.get("branch") else {
+        return Err(io::Error::other("no `syd try` is active"));
+    };
+    let reverted = match values.get("journal") {
+        Some(journal) => undo::undo_journal(&PathBuf::from(journal))?,
+        None => 0,
+    };
+    if let Some(folder) = values.get("folder") {
+        remove_worktree(&PathBuf::from(folder))?;
+    }
+    fs::remove_file(state::state_dir()?.join(TRY_STATE))?;
+    Ok((branch.clone(), reverted))
+}
+
+fn worktree_dir() -> io::Result<PathBuf> {
+    Ok(state::state_dir()?.join("try-worktree"))
+}
+
+fn remove_worktree(folder: &Path) -> io::Result<()> {
+    let worktree = worktree_dir()?;
+    if worktree.exists() {
+        fs::remove_dir_all(&worktree)?;
+    }
+    git::git(folder, &["worktree", "prune"]).map(|_| ())
+}
@@ -0,0 +1,218 @@
+// Checks on history fetched from the remote, run before any of it reaches the
+// backup folder and from there $HOME. They protect against a compromised
+// forge account pushing files that would otherwise be restored blindly.
+use crate::config::{expand_path, Config};
+use crate::git;
+use crate::state;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const REMOTE_STATE: &str = "remote";
+
+// `accept_rewrite` takes a rewritten remote branch as the new normal, for
+// when the user force-pushed it on purpose.
+pub fn verify_incoming(config: &Config, folder: &Path, accept_rewrite: bool) -> io::Result<()> {
+    let upstream = format!("origin/{}", config.branch());
+    if !git::succeeds(folder, &["rev-parse", "--verify", "--quiet", &upstream])? {
+        return Ok(());
+    }
+    if !accept_rewrite {
+        verify_not_rewritten(config, folder, &upstream)?;
+    }
+    let range = if git::succeeds(folder, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+        format!("HEAD..{}", upstream)
+    } else {
+        upstream
+    };
+    check_history(config, folder, &range)?;
+    remember_remote_head(config, folder)
+}
+
+// The configured signature and committer checks over the commits in `range`.
+pub fn check_history(config: &Config, folder: &Path, range: &str) -> io::Result<()> {
+    if config.settings.bool("git.require_signed").unwrap_or(false) {
+        verify_signatures(config, folder, range)?;
+    }
+    let allowed = config.settings.list("git.allowed_committers");
+    if !allowed.is_empty() {
+        verify_committers(&allowed, folder, range)?;
+    }
+    Ok(())
+}
+
+// Who signed the commits in `range`, as descriptions with commit counts,
+// most commits first.
+pub fn signers(config: &Config, folder: &Path, range: &str) -> io::Result<Vec<(String, usize)>> {
+    let mut command = git::command(folder);
+    if let Some(keyring) = config.settings.str("git.keyring") {
+        command.env("GNUPGHOME", import_keyring(&expand_path(keyring))?);
+    }
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for fields in log(command, range, "%H%x09%G?%x09%GS%x09%ae%x09%s")? {
+        let description = match fields[1].as_str() {
+            "G" | "U" => format!("signed by {} (author {})", fields[2], fields[3]),
+            status => format!("not verified, {} (author {})", signature_problem(status), fields[3]),
+        };
+        *counts.entry(description).or_default() += 1;
+    }
+    let mut signers: Vec<_> = counts.into_iter().collect();
+    signers.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    Ok(signers)
+}
+
+// The remote head seen last is kept per backup folder and branch, together
+// with the remote URL so that pointing syd elsewhere does not look like a
+// rewrite.
+fn last_seen(config: &Config, folder: &Path) -> io::Result<Option<String>> {
+    let seen = state::load(REMOTE_STATE)?;
+    let Some(value) = seen.get(&seen_key(config, folder)) else {
+        return Ok(None);
+    };
+    let (commit, remote) = value.split_once(' ').unwrap_or((value, ""));
+    Ok((Some(remote) == config.remote()).then(|| commit.to_string()))
+}
+
+fn seen_key(config: &Config, folder: &Path) -> String {
+    format!("{} {}", folder.display(), config.branch())
+}
+
+pub fn remember_remote_head(config: &Config, folder: &Path) -> io::Result<()> {
+    let upstream = format!("origin/{}", config.branch());
+    let Ok(head) = git::git(folder, &["rev-parse", "--verify", "--quiet", &upstream]) else {
+        return Ok(());
+    };
+    let mut seen = state::load(REMOTE_STATE)?;
+    seen.insert(seen_key(config, folder), format!("{} {}", head, config.remote().unwrap_or("")));
+    state::save(REMOTE_STATE, &seen)
+}
+
+// A remote branch that no longer contains the head seen last was force-pushed
+// or rewritten, which syd refuses to fast-forward onto without being told.
+fn verify_not_rewritten(config: &Config, folder: &Path, upstream: &str) -> io::Result<()> {
+    let Some(last) = last_seen(config, folder)? else {
+        return Ok(());
+    };
+    if git::succeeds(folder, &["merge-base", "--is-ancestor", &last, upstream])? {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "{} was rewritten: it no longer contains {}, the remote head seen last. \
+         Someone force-pushed or rewrote the history; if that was you, run `syd restore --accept-rewrite`",
+        upstream,
+        &last[..last.len().min(12)]
+    )))
+}
+
+// Every incoming commit needs a good signature. With `git.keyring` set only
+// the keys exported into that file count, otherwise the user's own keyring.
+fn verify_signatures(config: &Config, folder: &Path, range: &str) -> io::Result<()> {
+    let mut command = git::command(folder);
+    if let Some(keyring) = config.settings.str("git.keyring") {
+        command.env("GNUPGHOME", import_keyring(&expand_path(keyring))?);
+    }
+    for fields in log(command, range, "%H%x09%G?%x09%s")? {
+        // G is a good signature from a trusted key, U a good signature from a
+        // key gpg has no trust level for, which is every key in `git.keyring`.
+        if fields[1] != "G" && fields[1] != "U" {
+            return Err(refused(&fields, signature_problem(&fields[1])));
+        }
+    }
+    Ok(())
+}
+
+// Both the author and the committer of every incoming commit have to be on
+// the allowlist, given as email addresses or `@domain` suffixes.
+fn verify_committers(allowed: &[String], folder: &Path, range: &str) -> io::Result<()> {
+    let is_allowed = |email: &str| {
+        allowed.iter().any(|entry| {
+            if entry.starts_with('@') {
+                email.to_lowercase().ends_with(&entry.to_lowercase())
+            } else {
+                email.eq_ignore_ascii_case(entry)
+            }
+        })
+    };
+    for fields in log(git::command(folder), range, "%H%x09%ae%x09%ce%x09%s")? {
+        for (role, email) in [("author", &fields[1]), ("committer", &fields[2])] {
+            if !is_allowed(email) {
+                return Err(refused(&fields, &format!("its {} {} is not an allowed committer", role, email)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Runs `git log` over `range`, splitting each commit's line at tabs. The
+// format has to start with the hash and end with the subject, which may
+// contain tabs itself, as `refused` expects.
+fn log(mut command: Command, range: &str, format: &str) -> io::Result<Vec<Vec<String>>> {
+    let output = command.args(["log", &format!("--format={}", format), range]).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git log {} failed: {}",
+            range,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let fields = format.split("%x09").count();
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            let mut values: Vec<String> = line.splitn(fields, '\t').map(String::from).collect();
+            values.resize(fields, String::new());
+            values
+        })
+        .collect())
+}
+
+fn refused(fields: &[String], problem: &str) -> io::Error {
+    let commit = &fields[0];
+    io::Error::other(format!(
+        "refusing remote commit {} ({:?}): {}",
+        &commit[..commit.len().min(12)],
+        fields[fields.len() - 1],
+        problem
+    ))
+}
+
+fn signature_problem(status: &str) -> &'static str {
+    match status {
+        "N" => "it is not signed",
+        "B" => "its signature is bad",
+        "E" => "it is signed by a key that is not in the keyring",
+        "X" => "its signature has expired",
+        "Y" => "it is signed by an expired key",
+        "R" => "it is signed by a revoked key",
+        _ => "its signature could not be verified",
+    }
+}
+
+// Imports the trusted keys into a private gpg home in the state directory, so
+// keys in the user's own keyring are not trusted by accident.
+fn import_keyring(keyring: &Path) -> io::Result<PathBuf> {
+    let home = state::state_dir()?.join("keyring");
+    if home.exists() {
+        fs::remove_dir_all(&home)?;
+    }
+    fs::create_dir_all(&home)?;
+    fs::set_permissions(&home, fs::Permissions::from_mode(0o700))?;
+    let output = Command::new("gpg")
+        .arg("--homedir")
+        .arg(&home)
+        .args(["--batch", "--quiet", "--import"])
+        .arg(keyring)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run gpg: {}", e)))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "importing {} failed: {}",
+            keyring.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(home)
+}
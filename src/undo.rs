@@ -0,0 +1,222 @@
+use crate::files;
+use crate::owner;
+use crate::state;
+use crate::time;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const UNDO_DIR: &str = "undo";
+const JOURNAL_FILE: &str = "journal";
+// `index<TAB>owner<TAB>mode` for the files of entries with an `owner`.
+const OWNERS_FILE: &str = "owners";
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// Every restore writes a journal of the files it is about to touch, together
+// with copies of their previous content, so `syd undo` can put them back.
+pub struct Journal {
+    dir: PathBuf,
+    entries: usize,
+}
+
+impl Journal {
+    // Named `secs-nanos-pid` by the wall clock. A long-running daemon or web
+    // page restores many times, so an existing name is never reused.
+    pub fn begin() -> io::Result<Journal> {
+        let root = undo_root()?;
+        fs::create_dir_all(&root)?;
+        loop {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let name = format!("{}-{:09}-{}", now.as_secs(), now.subsec_nanos(), std::process::id());
+            let dir = root.join(name);
+            match fs::create_dir(&dir) {
+                Ok(()) => {
+                    fs::create_dir(dir.join("files"))?;
+                    return Ok(Journal { dir, entries: 0 });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Must be called before `target` is written. The journal line is flushed
+    // right away so an interrupted restore can still be undone. Files of
+    // another `owner` are saved readable only by syd's user.
+    pub fn record(&mut self, target: &Path, owner: Option<&str>) -> io::Result<()> {
+        let line = match fs::metadata(target) {
+            Ok(metadata) => {
+                let saved = self.dir.join("files").join(self.entries.to_string());
+                match owner::other(owner) {
+                    Some(owner) => {
+                        let content = owner::read(Some(owner), target)?;
+                        let mut copy =
+                            OpenOptions::new().create(true).truncate(true).write(true).mode(0o600).open(&saved)?;
+                        copy.write_all(&content)?;
+                        let mut owners = OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(self.dir.join(OWNERS_FILE))?;
+                        writeln!(owners, "{}\t{}\t{:o}", self.entries, owner, metadata.permissions().mode())?;
+                    }
+                    None => {
+                        fs::copy(target, saved)?;
+                    }
+                }
+                format!(
+                    "overwritten\t{}\t{}\t{}",
+                    self.entries,
+                    time::format_mtime(metadata.modified()?),
+                    target.display()
+                )
+            }
+            Err(_) => {
+                if let Some(owner) = owner::other(owner) {
+                    let mut owners = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(self.dir.join(OWNERS_FILE))?;
+                    writeln!(owners, "{}\t{}\t-", self.entries, owner)?;
+                }
+                format!("created\t{}\t-\t{}", self.entries, target.display())
+            }
+        };
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(JOURNAL_FILE))?;
+        writeln!(journal, "{}", line)?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Reverts everything recorded so far. The journal is kept when reverting
+    // fails, so `syd undo` can pick it up later.
+    pub fn rollback(self) -> io::Result<usize> {
+        let count = revert(&self.dir)?.len();
+        fs::remove_dir_all(&self.dir)?;
+        Ok(count)
+    }
+
+    // Returns the journal directory, or None when nothing was recorded.
+    pub fn finish(self) -> io::Result<Option<PathBuf>> {
+        if self.entries == 0 {
+            fs::remove_dir_all(&self.dir)?;
+            return Ok(None);
+        }
+        Ok(Some(self.dir))
+    }
+}
+
+// Returns the files put back.
+pub fn undo_last(retention: Duration) -> io::Result<Vec<PathBuf>> {
+    let Some((created, dir)) = records()?.pop() else {
+        return Err(io::Error::other("there is no restore to undo"));
+    };
+    if time::wall_now().saturating_sub(created) > retention.as_secs() {
+        return Err(io::Error::other(format!(
+            "the last restore ran at {} and is outside the undo retention window",
+            time::format_rfc3339(created)
+        )));
+    }
+    let reverted = revert(&dir)?;
+    fs::remove_dir_all(&dir)?;
+    Ok(reverted)
+}
+
+// Reverts one specific restore, as returned by `Journal::finish`.
+pub fn undo_journal(dir: &Path) -> io::Result<usize> {
+    if !dir.exists() {
+        return Err(io::Error::other(format!(
+            "the undo record {} no longer exists, it may have expired",
+            dir.display()
+        )));
+    }
+    let count = revert(dir)?.len();
+    fs::remove_dir_all(dir)?;
+    Ok(count)
+}
+
+pub fn prune(retention: Duration) -> io::Result<()> {
+    let now = time::wall_now();
+    for (created, dir) in records()? {
+        if now.saturating_sub(created) > retention.as_secs() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+    Ok(())
+}
+
+fn revert(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let journal = fs::read_to_string(dir.join(JOURNAL_FILE))?;
+    let owners: HashMap<String, (String, u32)> = fs::read_to_string(dir.join(OWNERS_FILE))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let (index, owner, mode) = (fields.next()?, fields.next()?, fields.next()?);
+            Some((index.to_string(), (owner.to_string(), u32::from_str_radix(mode, 8).unwrap_or(0o644))))
+        })
+        .collect();
+    let mut reverted = Vec::new();
+    for line in journal.lines().rev() {
+        let mut fields = line.splitn(4, '\t');
+        let (Some(kind), Some(index), Some(mtime), Some(target)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(io::Error::other(format!("corrupt undo journal in {}", dir.display())));
+        };
+        let target = Path::new(target);
+        let owner = owners.get(index).map(|(owner, _)| owner.as_str());
+        match kind {
+            "overwritten" => {
+                let saved = dir.join("files").join(index);
+                match owners.get(index) {
+                    Some((owner, mode)) => owner::write(owner, target, &fs::read(&saved)?, *mode)?,
+                    None => {
+                        files::copy_file(&saved, target)?;
+                    }
+                }
+                if let Some(mtime) = time::parse_mtime(mtime) {
+                    owner::set_mtime(owner, target, mtime)?;
+                }
+            }
+            "created" => owner::remove(owner, target)?,
+            other => return Err(io::Error::other(format!("unknown undo action {:?}", other))),
+        }
+        reverted.push(target.to_path_buf());
+    }
+    Ok(reverted)
+}
+
+fn undo_root() -> io::Result<PathBuf> {
+    Ok(state::state_dir()?.join(UNDO_DIR))
+}
+
+// Undo records sorted from oldest to newest. Records from before the
+// nanoseconds were part of the name are `secs-pid`.
+fn records() -> io::Result<Vec<(u64, PathBuf)>> {
+    let root = undo_root()?;
+    let Ok(dir) = fs::read_dir(&root) else {
+        return Ok(Vec::new());
+    };
+    let mut records = Vec::new();
+    for record in dir {
+        let record = record?;
+        let name = record.file_name().to_string_lossy().into_owned();
+        let fields: Vec<&str> = name.split('-').collect();
+        let Some(created) = fields[0].parse::<u64>().ok() else {
+            continue;
+        };
+        let nanos = match fields[..] {
+            [_, nanos, _] => nanos.parse::<u32>().unwrap_or(0),
+            _ => 0,
+        };
+        records.push(((created, nanos), record.path()));
+    }
+    records.sort();
+    Ok(records.into_iter().map(|((created, _), path)| (created, path)).collect())
+}
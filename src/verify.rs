@@ -0,0 +1,136 @@
+// `syd verify --remote-only URL` clones a backup repo into a temporary
+// directory and reports what restoring from it would do, without writing the
+// config, the backup folder or anything in $HOME. Meant for looking at an
+// unfamiliar mirror before trusting it.
+use crate::config::{self, Config, Entry, Table, Value};
+use crate::files;
+use crate::git;
+use crate::hash;
+use crate::manifest::Manifest;
+use crate::restore::Action;
+use crate::storage::{self, Storage};
+use crate::trust;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+pub struct RemoteFile {
+    pub target: PathBuf,
+    pub action: Action,
+    // Blob id of the restored content, or of the ciphertext when encrypted.
+    pub blob: String,
+    pub encrypted: bool,
+}
+
+pub struct VerifyReport {
+    pub head: String,
+    pub commits: usize,
+    pub signers: Vec<(String, usize)>,
+    // The error the configured trust checks would refuse the history with.
+    pub refused: Option<String>,
+    pub files: Vec<RemoteFile>,
+}
+
+// Uses the local config for the branch, trust settings and entries when
+// there is one; the repo's manifest takes priority for the entries.
+pub fn verify_remote(url: &str, branch: Option<&str>) -> io::Result<VerifyReport> {
+    let dir = env::temp_dir().join(format!("syd-verify-{}", process::id()));
+    let result = clone_and_inspect(url, branch, &dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    result
+}
+
+// Without a manifest or config every file in the repo is taken as a plain
+// copy at its place in the layout.
+fn guess_entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for relative in files::walk(dir)? {
+        if relative.to_string_lossy().starts_with(".syd-") {
+            continue;
+        }
+        let path = match relative.strip_prefix("_root") {
+            Ok(absolute) => Path::new("/").join(absolute),
+            Err(_) => config::home_dir().join(&relative),
+        };
+        entries.push(Entry {
+            path,
+            options: Table::default(),
+        });
+    }
+    Ok(entries)
+}
+
+fn clone_and_inspect(url: &str, branch: Option<&str>, dir: &Path) -> io::Result<VerifyReport> {
+    let mut config = config::load().unwrap_or_else(|_| Config {
+        path: PathBuf::new(),
+        settings: Table::default(),
+        entries: Vec::new(),
+        repos: Vec::new(),
+    });
+    if let Some(branch) = branch {
+        config.settings.insert("git.branch".to_string(), Value::Str(branch.to_string()));
+    }
+    config.settings.insert("git.remote".to_string(), Value::Str(url.to_string()));
+    config.settings.insert("backup.folder".to_string(), Value::Str(dir.to_string_lossy().into_owned()));
+
+    let output = Command::new("git")
+        .args(["clone", "--quiet", "--branch", config.branch(), "--", url])
+        .arg(dir)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git clone {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let head = git::git(dir, &["rev-parse", "HEAD"])?;
+    let commits = git::git(dir, &["rev-list", "--count", "HEAD"])?.parse().unwrap_or(0);
+    let signers = trust::signers(&config, dir, "HEAD")?;
+    let refused = trust::check_history(&config, dir, "HEAD").err().map(|e| e.to_string());
+
+    let entries = match Manifest::load(dir)? {
+        Some(manifest) => manifest.files.iter().map(|file| manifest.resolve(file)).collect(),
+        None if !config.entries.is_empty() => config.entries.clone(),
+        None => guess_entries(dir)?,
+    };
+    let mut files = Vec::new();
+    for entry in &entries {
+        if entry.options.str("submodule").is_some() {
+            continue;
+        }
+        for (target, source) in storage::stored_files(&config, entry, dir)? {
+            let encrypted = storage::storage(entry) == Storage::Encrypted;
+            let (blob, action) = if encrypted {
+                let action = if target.exists() { Action::Overwrite } else { Action::Create };
+                (hash::blob_id(&fs::read(&source)?), action)
+            } else {
+                let content = storage::load(&config, entry, &source)?;
+                let action = match fs::read(&target) {
+                    Ok(local) if local == content => Action::Skip,
+                    Ok(_) => Action::Overwrite,
+                    Err(_) => Action::Create,
+                };
+                (hash::blob_id(&content), action)
+            };
+            files.push(RemoteFile {
+                target,
+                action,
+                blob,
+                encrypted,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.target.cmp(&b.target));
+    Ok(VerifyReport {
+        head,
+        commits,
+        signers,
+        refused,
+        files,
+    })
+}
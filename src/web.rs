@@ -0,0 +1,301 @@
+// A small status page served by `syd daemon` when `daemon.web` is set, for
+// headless machines managed through a browser: what is out of sync, the
+// recent commits, and buttons to back up or restore single entries. It only
+// binds to loopback addresses; reach it from elsewhere with an SSH tunnel.
+// Forms carry a per-process token and the Host header is checked, so other
+// web pages open in the same browser cannot trigger backups or restores.
+use crate::backup::{self, BackupFlags};
+use crate::config;
+use crate::git;
+use crate::json;
+use crate::log;
+use crate::ops::Trigger;
+use crate::restore;
+use crate::status;
+use crate::store;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const MAX_BODY: usize = 64 * 1024;
+const RECENT_COMMITS: &str = "15";
+
+// The outcome of the last button press, shown once on the next page load.
+static NOTICE: Mutex<Option<String>> = Mutex::new(None);
+
+// Binds `address` and serves requests on a background thread.
+pub fn serve(address: &str) -> io::Result<()> {
+    let socket: SocketAddr = address
+        .parse()
+        .map_err(|_| io::Error::other(format!("daemon.web {:?} is not an address like 127.0.0.1:8421", address)))?;
+    if !socket.ip().is_loopback() {
+        return Err(io::Error::other(format!("daemon.web {} is not a loopback address", address)));
+    }
+    let listener = TcpListener::bind(socket)?;
+    let token = random_token()?;
+    log::info("web page listening", &[("address", json::string(address))]);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle(stream, socket, &token) {
+                log::warn(&format!("web request failed: {}", e), &[]);
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    body: String,
+}
+
+fn handle(mut stream: TcpStream, socket: SocketAddr, token: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let Some(request) = read_request(&stream)? else {
+        return respond(&mut stream, "400 Bad Request", "text/plain", "bad request\n");
+    };
+    let port = socket.port();
+    let allowed = [socket.to_string(), format!("localhost:{}", port), format!("127.0.0.1:{}", port)];
+    if !request.host.as_ref().is_some_and(|host| allowed.contains(host)) {
+        return respond(&mut stream, "403 Forbidden", "text/plain", "unexpected Host header\n");
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => {
+            let page = page(token).unwrap_or_else(|e| format!("<p>syd: {}</p>", escape(&e.to_string())));
+            respond(&mut stream, "200 OK", "text/html; charset=utf-8", &page)
+        }
+        ("POST", "/backup") | ("POST", "/restore") => {
+            let form = parse_form(&request.body);
+            if field(&form, "token") != Some(token) {
+                let message = "missing or stale token, reload the page\n";
+                return respond(&mut stream, "403 Forbidden", "text/plain", message);
+            }
+            let path = field(&form, "path").filter(|path| !path.is_empty()).map(config::expand_path);
+            let notice = match run_action(&request.path, path) {
+                Ok(notice) => notice,
+                Err(e) => {
+                    log::error(&e.to_string(), &[]);
+                    format!("Failed: {}", e)
+                }
+            };
+            *NOTICE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(notice);
+            stream.write_all(b"HTTP/1.1 303 See Other\r\nLocation: /\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    }
+}
+
+// The same runs as those of the daemon, so the trial guard, push windows,
+// metrics and notifications apply. Backing up one path narrows the config to
+// the entry holding it.
+fn run_action(action: &str, path: Option<PathBuf>) -> io::Result<String> {
+    let mut config = config::load()?;
+    if action == "/backup" {
+        if config.settings.str("daemon.mode") == Some("restore") {
+            return Err(io::Error::other("this machine only restores (daemon.mode = \"restore\")"));
+        }
+        if let Some(path) = &path {
+            config.entries.retain(|entry| path.starts_with(&entry.path));
+        }
+        let flags = BackupFlags {
+            unattended: true,
+            trigger: Trigger::Web,
+            ..BackupFlags::default()
+        };
+        let report = backup::run(&config, &flags)?;
+        return Ok(match report.committed {
+            true => format!("Backed up {} file(s)", report.files_changed),
+            false => "Nothing to back up".to_string(),
+        });
+    }
+    let only: Vec<PathBuf> = path.into_iter().collect();
+    let report = restore::pull_and_restore(&config, &only, Trigger::Web)?;
+    Ok(format!("Restored {} file(s), {} already up to date", report.restored, report.unchanged))
+}
+
+fn page(token: &str) -> io::Result<String> {
+    let config = config::load()?;
+    let report = status::status(&config)?;
+    let origins = status::origins(&config)?;
+    let mut html = format!(
+        "<!doctype html><meta charset=utf-8><title>syd on {host}</title>\
+         <style>body{{font-family:sans-serif;margin:2em}}td{{padding:0 1em 0 0}}form{{display:inline}}</style>\
+         <h1>syd on {host}</h1>",
+        host = escape(&git::hostname())
+    );
+    if let Some(notice) = NOTICE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+        html.push_str(&format!("<p><strong>{}</strong></p>", escape(&notice)));
+    }
+
+    html.push_str("<h2>Status</h2>");
+    if report.changes.is_empty() && report.unpushed == 0 {
+        html.push_str("<p>Everything is backed up</p>");
+    }
+    if report.unpushed > 0 {
+        html.push_str(&format!("<p>{} commit(s) not pushed yet</p>", report.unpushed));
+    }
+    html.push_str(&button(token, "/backup", None, "Back up everything"));
+    html.push_str(&button(token, "/restore", None, "Restore everything"));
+
+    html.push_str("<h2>Files</h2><table>");
+    let mut paths = restore::tracked_files(&config)?;
+    for (_, path) in &report.changes {
+        if !paths.contains(path) {
+            paths.push(path.clone());
+        }
+    }
+    paths.sort();
+    for path in &paths {
+        let change = report.changes.iter().find(|(_, changed)| changed == path).map(|(change, _)| change.label());
+        let origin = origins
+            .get(path)
+            .map(|origin| format!("{} {}", origin.host, &origin.date[..10]))
+            .unwrap_or_default();
+        let name = config::display_path(path);
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}{}</td></tr>",
+            escape(&name),
+            change.unwrap_or("backed up"),
+            escape(&origin),
+            button(token, "/backup", Some(&name), "Back up"),
+            button(token, "/restore", Some(&name), "Restore")
+        ));
+    }
+    html.push_str("</table><h2>Recent commits</h2>");
+    for repo in config.repo_configs()? {
+        let folder = repo.backup_folder();
+        let has_head = git::succeeds(&folder, &["rev-parse", "--verify", "--quiet", "HEAD"]).unwrap_or(false);
+        if !store::is_git(&repo) || !has_head {
+            continue;
+        }
+        html.push_str(&format!("<h3>{}</h3><table>", escape(&config::display_path(&folder))));
+        let log = git::git(&folder, &["log", "-n", RECENT_COMMITS, "--format=%h%x1f%cI%x1f%s"])?;
+        for line in log.lines() {
+            let fields: Vec<&str> = line.split('\x1f').collect();
+            if let [hash, date, subject] = fields[..] {
+                html.push_str(&format!(
+                    "<tr><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+                    hash,
+                    escape(date),
+                    escape(subject)
+                ));
+            }
+        }
+        html.push_str("</table>");
+    }
+    Ok(html)
+}
+
+fn button(token: &str, action: &str, path: Option<&str>, label: &str) -> String {
+    format!(
+        "<form method=post action={}><input type=hidden name=token value={}>\
+         <input type=hidden name=path value=\"{}\"><button>{}</button></form> ",
+        action,
+        token,
+        escape(path.unwrap_or("")),
+        label
+    )
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut host = None;
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "host" => host = Some(value.trim().to_string()),
+                "content-length" => length = value.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Ok(None);
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        host,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    }))
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+// application/x-www-form-urlencoded
+fn parse_form(body: &str) -> Vec<(String, String)> {
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (decode(key), decode(value)))
+        .collect()
+}
+
+fn field<'a>(form: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    form.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn random_token() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}